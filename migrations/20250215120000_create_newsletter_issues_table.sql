@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE
+    newsletter_issues (
+        newsletter_issue_id uuid NOT NULL,
+        title TEXT NOT NULL,
+        text_content TEXT NOT NULL,
+        html_content TEXT NOT NULL,
+        published_at timestamptz NOT NULL,
+        PRIMARY KEY (newsletter_issue_id)
+    );
+
+CREATE TABLE
+    issue_deliveries (
+        newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+        subscriber_email TEXT NOT NULL,
+        status TEXT NOT NULL,
+        attempts INT NOT NULL DEFAULT 0,
+        last_error TEXT NULL,
+        updated_at timestamptz NOT NULL,
+        PRIMARY KEY (newsletter_issue_id, subscriber_email)
+    );
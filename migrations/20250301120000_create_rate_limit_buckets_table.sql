@@ -0,0 +1,10 @@
+-- Token buckets shared by every instance when `rate_limit.store` is `postgres`
+CREATE TABLE
+    rate_limit_buckets (
+        key TEXT NOT NULL,
+        tokens DOUBLE PRECISION NOT NULL,
+        updated_at timestamptz NOT NULL,
+        PRIMARY KEY (key)
+    );
+
+CREATE INDEX rate_limit_buckets_updated_at_idx ON rate_limit_buckets (updated_at);
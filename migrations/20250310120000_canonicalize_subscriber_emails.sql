@@ -0,0 +1,47 @@
+-- Store subscriber emails in canonical form (trimmed, lower-cased domain),
+-- keep the address as typed for display, and make the canonical form unique
+-- regardless of case.
+--
+-- Internationalized domains cannot be punycode-encoded from SQL: rows saved
+-- before this migration keep them as they are.
+ALTER TABLE subscriptions ADD COLUMN display_email TEXT;
+
+UPDATE subscriptions SET display_email = btrim(email);
+
+ALTER TABLE subscriptions ALTER COLUMN display_email SET NOT NULL;
+
+-- Subscribers whose addresses only differ in case must be merged by hand
+-- before the unique index can be created. List them all and fail, before
+-- canonicalizing runs into the old case-sensitive constraint.
+DO $$
+DECLARE
+    conflicts TEXT;
+BEGIN
+    SELECT
+        string_agg(addresses, E'\n  ' ORDER BY addresses) INTO conflicts
+    FROM
+        (
+            SELECT
+                string_agg(display_email || ' (' || id || ', ' || status || ')', ', ' ORDER BY subscribed_at) AS addresses
+            FROM
+                subscriptions
+            GROUP BY
+                lower(btrim(email))
+            HAVING
+                COUNT(*) > 1
+        ) AS duplicates;
+
+    IF conflicts IS NOT NULL THEN
+        RAISE EXCEPTION E'Some subscribers share an email address once case is ignored. Remove or merge the duplicates, e.g. with `zero2prod subscribers remove`, then run the migration again:\n  %', conflicts;
+    END IF;
+END $$;
+
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+
+UPDATE subscriptions
+SET
+    email = substring(btrim(email) FROM '^(.*)@') || '@' || lower(substring(btrim(email) FROM '@([^@]*)$'))
+WHERE
+    email LIKE '%@%';
+
+CREATE UNIQUE INDEX subscriptions_email_key ON subscriptions (lower(email));
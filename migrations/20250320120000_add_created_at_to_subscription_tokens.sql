@@ -0,0 +1,4 @@
+-- Confirmation links expire after `confirmation.token_ttl_hours`. Tokens issued
+-- before this migration count from when it ran.
+ALTER TABLE subscription_tokens
+ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
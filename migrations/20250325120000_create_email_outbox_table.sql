@@ -0,0 +1,20 @@
+-- Transactional emails, written in the same transaction as the change that
+-- triggers them and sent by the relay worker.
+CREATE TABLE
+    email_outbox (
+        id uuid NOT NULL,
+        recipient TEXT NOT NULL,
+        subject TEXT NOT NULL,
+        html_content TEXT NOT NULL,
+        text_content TEXT NOT NULL,
+        status TEXT NOT NULL,
+        attempts INT NOT NULL DEFAULT 0,
+        last_error TEXT NULL,
+        next_attempt_at timestamptz NOT NULL,
+        created_at timestamptz NOT NULL,
+        updated_at timestamptz NOT NULL,
+        PRIMARY KEY (id)
+    );
+
+CREATE INDEX email_outbox_pending_idx ON email_outbox (next_attempt_at)
+WHERE status = 'pending';
@@ -0,0 +1,8 @@
+-- Tokens are now stored as an HMAC-SHA256 of the token, keyed with
+-- `confirmation.token_key`. The key is not available here, so existing tokens
+-- cannot be rehashed: they are dropped. Pending subscribers can ask for a new
+-- link at `/subscriptions/resend-confirmation`.
+DELETE FROM subscription_tokens;
+
+ALTER TABLE subscription_tokens
+RENAME COLUMN subscription_token TO token_hash;
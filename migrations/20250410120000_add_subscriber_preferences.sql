@@ -0,0 +1,17 @@
+-- Preferences subscribers manage from `/preferences`.
+ALTER TABLE subscriptions
+ADD COLUMN email_format TEXT NOT NULL DEFAULT 'html' CHECK (email_format IN ('html', 'plain_text')),
+ADD COLUMN frequency TEXT NOT NULL DEFAULT 'every_issue' CHECK (frequency IN ('every_issue', 'weekly_digest'));
+
+-- The topics a subscriber picked out of `topics` in the configuration. A
+-- subscriber without any receives every topic.
+CREATE TABLE
+    subscriber_topics (
+        subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+        topic TEXT NOT NULL,
+        PRIMARY KEY (subscriber_id, topic)
+    );
+
+-- Issues without a topic go to everyone.
+ALTER TABLE newsletter_issues
+ADD COLUMN topic TEXT NULL;
@@ -0,0 +1,24 @@
+-- What audience segments filter on, besides `subscribed_at`.
+ALTER TABLE subscriptions
+ADD COLUMN signup_source TEXT NULL;
+
+CREATE TABLE
+    subscriber_tags (
+        subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (subscriber_id, tag)
+    );
+
+CREATE INDEX subscriber_tags_tag_idx ON subscriber_tags (tag);
+
+CREATE TABLE
+    subscriber_attributes (
+        subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (subscriber_id, key)
+    );
+
+-- The segment an issue was sent to, as written by its author.
+ALTER TABLE newsletter_issues
+ADD COLUMN segment TEXT NULL;
@@ -0,0 +1,4 @@
+-- When the last weekly digest was put together for a subscriber. The next one
+-- covers the issues published since. Falls back to `subscribed_at`.
+ALTER TABLE subscriptions
+ADD COLUMN last_digest_at TIMESTAMPTZ NULL;
@@ -0,0 +1,19 @@
+-- Public issues are listed in the archive and the feeds, under their slug.
+ALTER TABLE newsletter_issues
+ADD COLUMN public BOOLEAN NOT NULL DEFAULT FALSE,
+ADD COLUMN slug TEXT NULL;
+
+-- Issues published before slugs existed stay private: the suffix keeps their
+-- slugs unique if they are made public later.
+UPDATE newsletter_issues
+SET slug = COALESCE(
+        NULLIF(trim(BOTH '-' FROM regexp_replace(lower(title), '[^a-z0-9]+', '-', 'g')), ''),
+        'issue'
+    ) || '-' || left(newsletter_issue_id::text, 8);
+
+ALTER TABLE newsletter_issues
+ALTER COLUMN slug SET NOT NULL,
+ADD CONSTRAINT newsletter_issues_slug_key UNIQUE (slug);
+
+CREATE INDEX newsletter_issues_public_idx ON newsletter_issues (published_at)
+WHERE public;
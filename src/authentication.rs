@@ -0,0 +1,47 @@
+use std::future::{ready, Ready};
+
+use actix_web::{dev::Payload, http::header::AUTHORIZATION, web, FromRequest, HttpRequest};
+use secrecy::{ExposeSecret, SecretString};
+use subtle::ConstantTimeEq;
+
+use crate::errors::AdminAuthError;
+
+/// The bearer token that grants access to the `/admin` endpoints. They reject
+/// every request if no token is configured.
+pub struct AdminToken(pub Option<SecretString>);
+
+/// An operator authenticated with the admin token. Add it as a handler
+/// argument to restrict the handler to operators.
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Error = AdminAuthError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(authenticate(request))
+    }
+}
+
+fn authenticate(request: &HttpRequest) -> Result<Admin, AdminAuthError> {
+    let expected = request
+        .app_data::<web::Data<AdminToken>>()
+        .and_then(|token| token.0.as_ref())
+        .ok_or(AdminAuthError::Disabled)?;
+    let provided = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(AdminAuthError::MissingCredentials)?;
+
+    if bool::from(
+        provided
+            .as_bytes()
+            .ct_eq(expected.expose_secret().as_bytes()),
+    ) {
+        Ok(Admin)
+    } else {
+        Err(AdminAuthError::InvalidCredentials)
+    }
+}
@@ -0,0 +1,301 @@
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::configuration::{BotProtectionSettings, CaptchaProvider, CaptchaSettings};
+
+/// The anti-bot fields of the subscription form. Humans leave `website` empty:
+/// it is hidden from them.
+#[derive(Deserialize, Default)]
+pub struct BotCheckFields {
+    #[serde(default)]
+    pub website: Option<String>,
+    #[serde(default)]
+    pub form_token: Option<String>,
+    #[serde(default, alias = "h-captcha-response", alias = "cf-turnstile-response")]
+    pub captcha_response: Option<String>,
+}
+
+/// The outcome of checking a submission.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Human,
+    /// A bot, for the reason given.
+    Bot(&'static str),
+}
+
+/// Checks that a CAPTCHA was solved by a human.
+#[async_trait]
+pub trait CaptchaVerifier: Send + Sync {
+    /// Whether `response`, produced by the CAPTCHA widget in the client's
+    /// browser, is valid.
+    async fn verify(
+        &self,
+        response: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<bool, anyhow::Error>;
+}
+
+/// Verifies CAPTCHAs with the `siteverify` API shared by hCaptcha and
+/// Cloudflare Turnstile.
+pub struct HttpCaptchaVerifier {
+    http_client: reqwest::Client,
+    verify_url: String,
+    secret: SecretString,
+}
+
+#[derive(Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+impl HttpCaptchaVerifier {
+    pub fn new(settings: &CaptchaSettings) -> Result<Self, reqwest::Error> {
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(settings.timeout_milliseconds))
+            .build()?;
+        Ok(Self {
+            http_client,
+            verify_url: settings
+                .verify_url
+                .clone()
+                .unwrap_or_else(|| settings.provider.verify_url().to_string()),
+            secret: settings.secret.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl CaptchaVerifier for HttpCaptchaVerifier {
+    #[tracing::instrument(name = "Verifying a CAPTCHA", skip(self, response))]
+    async fn verify(
+        &self,
+        response: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<bool, anyhow::Error> {
+        let mut form = vec![
+            ("secret", self.secret.expose_secret().to_string()),
+            ("response", response.to_string()),
+        ];
+        if let Some(ip) = client_ip {
+            form.push(("remoteip", ip.to_string()));
+        }
+        let outcome = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&form)
+            .send()
+            .await
+            .context("Failed to reach the CAPTCHA verification API.")?
+            .error_for_status()
+            .context("The CAPTCHA verification API returned an error.")?
+            .json::<SiteVerifyResponse>()
+            .await
+            .context("Failed to parse the CAPTCHA verification response.")?;
+        Ok(outcome.success)
+    }
+}
+
+/// The CAPTCHA widget to embed in the form, and how to verify its responses.
+pub struct Captcha {
+    pub provider: CaptchaProvider,
+    pub site_key: String,
+    verifier: Arc<dyn CaptchaVerifier>,
+}
+
+/// Tells humans and bots apart on the subscription form, with a honeypot
+/// field, a signed timestamp of when the form was rendered and, optionally,
+/// a CAPTCHA.
+pub struct BotProtection {
+    signing_key: SecretString,
+    min_submit_age: Duration,
+    max_form_age: Duration,
+    pub captcha: Option<Captcha>,
+}
+
+impl BotProtection {
+    /// `None` if bot protection is disabled.
+    pub fn from_settings(settings: &BotProtectionSettings) -> Result<Option<Self>, anyhow::Error> {
+        if !settings.enabled {
+            return Ok(None);
+        }
+        let signing_key = settings
+            .signing_key
+            .clone()
+            .context("Bot protection requires a signing key.")?;
+        let captcha = match &settings.captcha {
+            Some(captcha) => Some(Captcha {
+                provider: captcha.provider,
+                site_key: captcha.site_key.clone(),
+                verifier: Arc::new(
+                    HttpCaptchaVerifier::new(captcha)
+                        .context("Failed to build the CAPTCHA verifier.")?,
+                ),
+            }),
+            None => None,
+        };
+        Ok(Some(Self {
+            signing_key,
+            min_submit_age: Duration::from_secs(settings.min_submit_seconds),
+            max_form_age: Duration::from_secs(settings.max_form_age_seconds),
+            captcha,
+        }))
+    }
+
+    /// A token recording that the form was rendered at `now`.
+    pub fn issue_form_token(&self, now: DateTime<Utc>) -> String {
+        let timestamp = now.timestamp();
+        format!("{timestamp}.{}", hex::encode(self.sign(timestamp)))
+    }
+
+    #[tracing::instrument(name = "Checking a subscription for bots", skip(self, fields))]
+    pub async fn check(
+        &self,
+        fields: &BotCheckFields,
+        client_ip: Option<IpAddr>,
+        now: DateTime<Utc>,
+    ) -> Result<Verdict, anyhow::Error> {
+        if fields.website.as_deref().is_some_and(|v| !v.is_empty()) {
+            return Ok(Verdict::Bot("filled in the honeypot field"));
+        }
+        if let Some(reason) = self.check_form_token(fields.form_token.as_deref(), now) {
+            return Ok(Verdict::Bot(reason));
+        }
+        if let Some(captcha) = &self.captcha {
+            let Some(response) = fields.captcha_response.as_deref() else {
+                return Ok(Verdict::Bot("did not solve the CAPTCHA"));
+            };
+            if !captcha.verifier.verify(response, client_ip).await? {
+                return Ok(Verdict::Bot("failed the CAPTCHA"));
+            }
+        }
+        Ok(Verdict::Human)
+    }
+
+    /// Why the form token gives the submitter away as a bot, if it does.
+    fn check_form_token(&self, token: Option<&str>, now: DateTime<Utc>) -> Option<&'static str> {
+        let Some((timestamp, signature)) = token.and_then(|t| t.split_once('.')) else {
+            return Some("did not send a form token");
+        };
+        let (Ok(timestamp), Ok(signature)) = (timestamp.parse::<i64>(), hex::decode(signature))
+        else {
+            return Some("sent a malformed form token");
+        };
+        if self.mac(timestamp).verify_slice(&signature).is_err() {
+            return Some("sent a forged form token");
+        }
+
+        let age = now.timestamp() - timestamp;
+        if age < self.min_submit_age.as_secs() as i64 {
+            Some("submitted the form too quickly")
+        } else if age > self.max_form_age.as_secs() as i64 {
+            Some("sent an expired form token")
+        } else {
+            None
+        }
+    }
+
+    fn sign(&self, timestamp: i64) -> Vec<u8> {
+        self.mac(timestamp).finalize().into_bytes().to_vec()
+    }
+
+    fn mac(&self, timestamp: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.signing_key.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(timestamp.to_string().as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use super::{BotCheckFields, BotProtection, Verdict};
+
+    fn bot_protection() -> BotProtection {
+        BotProtection {
+            signing_key: "a-signing-key-that-is-long-enough".into(),
+            min_submit_age: Duration::from_secs(3),
+            max_form_age: Duration::from_secs(3600),
+            captcha: None,
+        }
+    }
+
+    fn fields(form_token: String) -> BotCheckFields {
+        BotCheckFields {
+            form_token: Some(form_token),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_token_submitted_after_the_minimum_delay_is_accepted() {
+        let bot_protection = bot_protection();
+        let rendered_at = Utc::now();
+        let token = bot_protection.issue_form_token(rendered_at);
+
+        let verdict = bot_protection
+            .check(&fields(token), None, rendered_at + Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        assert_eq!(verdict, Verdict::Human);
+    }
+
+    #[tokio::test]
+    async fn instant_submissions_are_bots() {
+        let bot_protection = bot_protection();
+        let rendered_at = Utc::now();
+        let token = bot_protection.issue_form_token(rendered_at);
+
+        let verdict = bot_protection
+            .check(&fields(token), None, rendered_at)
+            .await
+            .unwrap();
+
+        assert_eq!(verdict, Verdict::Bot("submitted the form too quickly"));
+    }
+
+    #[tokio::test]
+    async fn tokens_are_rejected_once_expired() {
+        let bot_protection = bot_protection();
+        let rendered_at = Utc::now();
+        let token = bot_protection.issue_form_token(rendered_at);
+
+        let verdict = bot_protection
+            .check(
+                &fields(token),
+                None,
+                rendered_at + Duration::from_secs(7200),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(verdict, Verdict::Bot("sent an expired form token"));
+    }
+
+    #[tokio::test]
+    async fn backdated_tokens_are_rejected() {
+        let bot_protection = bot_protection();
+        let now = Utc::now();
+        let token = bot_protection.issue_form_token(now);
+        let (_, signature) = token.split_once('.').unwrap();
+        let backdated = format!("{}.{signature}", now.timestamp() - 60);
+
+        let verdict = bot_protection
+            .check(&fields(backdated), None, now)
+            .await
+            .unwrap();
+
+        assert_eq!(verdict, Verdict::Bot("sent a forged form token"));
+    }
+}
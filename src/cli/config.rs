@@ -0,0 +1,223 @@
+use std::str::FromStr;
+
+use anyhow::Context;
+use clap::Subcommand;
+use serde::Serialize;
+use sqlx::postgres::PgConnectOptions;
+
+use super::output::{print_records, OutputFormat, Tabular};
+use crate::{
+    configuration::{
+        configuration_directory, flatten, get_environment, load_configuration, Environment,
+        InvalidConfiguration, Settings, SECRET_KEYS,
+    },
+    migrations::{migration_status, MigrationState},
+    startup::get_connection_pool,
+};
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Check that the configuration is usable: every setting is valid, the
+    /// database is reachable and its schema is up to date. Lists every
+    /// problem rather than stopping at the first one. A setting of the wrong
+    /// type is reported for its section, and the database checks are skipped.
+    Check,
+    /// Print the merged configuration with secrets masked, along with the
+    /// layer each value came from. Works even if the configuration is invalid.
+    Show,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    key: String,
+    value: String,
+    origin: String,
+}
+
+impl Tabular for Entry {
+    fn headers() -> &'static [&'static str] {
+        &["KEY", "VALUE", "ORIGIN"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.key.clone(), self.value.clone(), self.origin.clone()]
+    }
+}
+
+#[derive(Serialize)]
+struct Check {
+    name: &'static str,
+    ok: bool,
+    detail: String,
+}
+
+impl Tabular for Check {
+    fn headers() -> &'static [&'static str] {
+        &["CHECK", "STATUS", "DETAIL"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.to_string(),
+            if self.ok { "ok" } else { "FAILED" }.to_string(),
+            self.detail.clone(),
+        ]
+    }
+}
+
+impl Check {
+    fn new(name: &'static str, outcome: Result<String, String>) -> Self {
+        match outcome {
+            Ok(detail) => Self {
+                name,
+                ok: true,
+                detail,
+            },
+            Err(detail) => Self {
+                name,
+                ok: false,
+                detail,
+            },
+        }
+    }
+}
+
+/// Unlike the other commands these do not need valid [`Settings`], since they
+/// are how a broken configuration gets debugged.
+pub async fn run(command: ConfigCommand, format: OutputFormat) -> Result<(), anyhow::Error> {
+    match command {
+        ConfigCommand::Check => {
+            let checks = match load_settings() {
+                Ok((configuration, environment)) => {
+                    let mut checks = match configuration.validate(&environment) {
+                        Ok(()) => vec![Check::new(
+                            "settings",
+                            Ok(format!("valid for {}", environment.as_str())),
+                        )],
+                        Err(invalid) => problem_checks(invalid),
+                    };
+                    checks.extend(check_configuration(&configuration).await);
+                    checks
+                }
+                Err(checks) => checks,
+            };
+            print_records(format, &checks)?;
+            let failed = checks.iter().filter(|c| !c.ok).count();
+            if failed > 0 {
+                anyhow::bail!("{failed} configuration check(s) failed.");
+            }
+        }
+        ConfigCommand::Show => show(format)?,
+    }
+
+    Ok(())
+}
+
+/// The settings, or a failed check for every section that could not be
+/// deserialized. The checks that need a connection are skipped then.
+fn load_settings() -> Result<(Settings, Environment), Vec<Check>> {
+    let failed = |e: &dyn std::fmt::Display| vec![Check::new("settings", Err(e.to_string()))];
+    let environment = get_environment().map_err(|e| failed(&e))?;
+    let configuration =
+        configuration_directory()
+            .map_err(|e| failed(&e))
+            .and_then(|directory| {
+                load_configuration(&directory, &environment).map_err(|e| failed(&e))
+            })?;
+    match configuration.clone().try_deserialize::<Settings>() {
+        Ok(settings) => Ok((settings, environment)),
+        Err(e) => {
+            let invalid = InvalidConfiguration::from_type_errors(&configuration);
+            if invalid.problems.is_empty() {
+                Err(failed(&e))
+            } else {
+                Err(problem_checks(invalid))
+            }
+        }
+    }
+}
+
+fn problem_checks(invalid: InvalidConfiguration) -> Vec<Check> {
+    invalid
+        .problems
+        .into_iter()
+        .map(|(key, problem)| Check::new(key, Err(problem)))
+        .collect()
+}
+
+/// Print every merged configuration value.
+fn show(format: OutputFormat) -> Result<(), anyhow::Error> {
+    let environment = get_environment()?;
+    let configuration = load_configuration(&configuration_directory()?, &environment)
+        .context("Failed to load the configuration.")?;
+    let entries: Vec<_> = flatten(configuration)?
+        .into_iter()
+        .map(|(key, value)| Entry {
+            origin: value.origin().unwrap_or("default").to_string(),
+            value: if key == "database.database_url" {
+                redact_database_url(&value.to_string())
+            } else if SECRET_KEYS.contains(&key.as_str()) {
+                "********".to_string()
+            } else {
+                value.to_string()
+            },
+            key,
+        })
+        .collect();
+    print_records(format, &entries)
+}
+
+/// The connection string with its password masked, so that where it points
+/// is still visible.
+fn redact_database_url(url: &str) -> String {
+    match PgConnectOptions::from_str(url) {
+        Ok(options) => format!(
+            "postgres://{}:********@{}:{}/{}",
+            options.get_username(),
+            options.get_host(),
+            options.get_port(),
+            options.get_database().unwrap_or_default()
+        ),
+        Err(_) => "********".to_string(),
+    }
+}
+
+async fn check_configuration(configuration: &Settings) -> Vec<Check> {
+    let email_client = &configuration.email_client;
+    let mut checks = vec![Check::new(
+        "email_client",
+        email_client
+            .clone()
+            .client()
+            .map(|_| format!("sending through {}", email_client.base_url)),
+    )];
+
+    let db_pool = get_connection_pool(&configuration.database);
+    let database = sqlx::query("SELECT 1")
+        .execute(&db_pool)
+        .await
+        .map(|_| format!("connected to {}", configuration.database.target()))
+        .map_err(|e| e.to_string());
+    let reachable = database.is_ok();
+    checks.push(Check::new("database", database));
+
+    if reachable {
+        let migrations = migration_status(&db_pool)
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|status| {
+                let pending = status
+                    .iter()
+                    .filter(|m| m.state != MigrationState::Applied)
+                    .count();
+                if pending == 0 {
+                    Ok(format!("{} migration(s) applied", status.len()))
+                } else {
+                    Err(format!("{pending} migration(s) pending or modified"))
+                }
+            });
+        checks.push(Check::new("migrations", migrations));
+    }
+
+    checks
+}
@@ -0,0 +1,233 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::Subcommand;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::output::{print_message, print_record, print_records, OutputFormat, Tabular};
+use crate::{
+    configuration::Settings,
+    digest::enqueue_due_digests,
+    email_outbox::relay_pending_emails,
+    issue_delivery::{deliver_issue, retry_failed_deliveries, DeliveryReport},
+    startup::get_connection_pool,
+    subscription_tokens::TokenHasher,
+};
+
+#[derive(Subcommand)]
+pub enum IssuesCommand {
+    /// List published issues with their delivery progress.
+    List,
+    /// Show an issue and the state of each of its deliveries.
+    Show { id: Uuid },
+    /// Send the pending deliveries of an issue.
+    Send { id: Uuid },
+    /// Retry the deliveries of an issue that previously failed.
+    RetryFailed { id: Uuid },
+    /// Send the weekly digests that are due.
+    SendDigests,
+}
+
+#[derive(Serialize)]
+struct IssueSummary {
+    id: Uuid,
+    title: String,
+    published_at: DateTime<Utc>,
+    sent: i64,
+    pending: i64,
+    failed: i64,
+}
+
+impl Tabular for IssueSummary {
+    fn headers() -> &'static [&'static str] {
+        &["ID", "TITLE", "PUBLISHED AT", "SENT", "PENDING", "FAILED"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.title.clone(),
+            self.published_at.to_rfc3339(),
+            self.sent.to_string(),
+            self.pending.to_string(),
+            self.failed.to_string(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct Delivery {
+    subscriber_email: String,
+    status: String,
+    attempts: i32,
+    last_error: Option<String>,
+}
+
+impl Tabular for Delivery {
+    fn headers() -> &'static [&'static str] {
+        &["EMAIL", "STATUS", "ATTEMPTS", "LAST ERROR"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.subscriber_email.clone(),
+            self.status.clone(),
+            self.attempts.to_string(),
+            self.last_error.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct IssueDetails {
+    #[serde(flatten)]
+    summary: IssueSummary,
+    deliveries: Vec<Delivery>,
+}
+
+pub async fn run(
+    command: IssuesCommand,
+    configuration: &Settings,
+    format: OutputFormat,
+) -> Result<(), anyhow::Error> {
+    let db_pool = get_connection_pool(&configuration.database);
+
+    match command {
+        IssuesCommand::List => {
+            let issues = list_issues(&db_pool, None)
+                .await
+                .context("Failed to list newsletter issues.")?;
+            print_records(format, &issues)?;
+        }
+        IssuesCommand::Show { id } => {
+            let summary = list_issues(&db_pool, Some(id))
+                .await
+                .context("Failed to retrieve newsletter issue.")?
+                .pop()
+                .with_context(|| format!("Newsletter issue {id} does not exist."))?;
+            let deliveries = list_deliveries(&db_pool, id)
+                .await
+                .context("Failed to list issue deliveries.")?;
+            let details = IssueDetails {
+                summary,
+                deliveries,
+            };
+            match format {
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&details)?),
+                OutputFormat::Table => {
+                    print_record(format, &details.summary)?;
+                    println!();
+                    print_records(format, &details.deliveries)?;
+                }
+            }
+        }
+        IssuesCommand::Send { id } => {
+            let email_client = configuration
+                .email_client
+                .clone()
+                .client()
+                .map_err(anyhow::Error::msg)?;
+            let token_hasher = TokenHasher::from_settings(&configuration.confirmation)?;
+            let report = deliver_issue(
+                &db_pool,
+                &email_client,
+                &configuration.application.base_url,
+                &token_hasher,
+                id,
+            )
+            .await?;
+            print_report(format, report);
+        }
+        IssuesCommand::RetryFailed { id } => {
+            let email_client = configuration
+                .email_client
+                .clone()
+                .client()
+                .map_err(anyhow::Error::msg)?;
+            let token_hasher = TokenHasher::from_settings(&configuration.confirmation)?;
+            let report = retry_failed_deliveries(
+                &db_pool,
+                &email_client,
+                &configuration.application.base_url,
+                &token_hasher,
+                id,
+            )
+            .await?;
+            print_report(format, report);
+        }
+        IssuesCommand::SendDigests => {
+            let token_hasher = TokenHasher::from_settings(&configuration.confirmation)?;
+            let report = enqueue_due_digests(
+                &db_pool,
+                &configuration.application.base_url,
+                &token_hasher,
+                &configuration.digest,
+            )
+            .await?;
+            // Send them right away rather than wait for a server to relay them.
+            let email_client = configuration
+                .email_client
+                .clone()
+                .client()
+                .map_err(anyhow::Error::msg)?;
+            relay_pending_emails(&db_pool, &email_client, &configuration.email_outbox)
+                .await
+                .context("Failed to relay the email outbox.")?;
+            print_message(
+                format,
+                &format!(
+                    "Sent {} digest(s), skipped {} with nothing new.",
+                    report.sent, report.skipped
+                ),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn print_report(format: OutputFormat, report: DeliveryReport) {
+    print_message(
+        format,
+        &format!("Sent {}, failed {}.", report.sent, report.failed),
+    );
+}
+
+async fn list_issues(db_pool: &PgPool, id: Option<Uuid>) -> Result<Vec<IssueSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT
+            i.newsletter_issue_id AS id,
+            i.title,
+            i.published_at,
+            COUNT(*) FILTER (WHERE d.status = 'sent') AS "sent!",
+            COUNT(*) FILTER (WHERE d.status = 'pending') AS "pending!",
+            COUNT(*) FILTER (WHERE d.status = 'failed') AS "failed!"
+        FROM newsletter_issues i
+        LEFT JOIN issue_deliveries d USING (newsletter_issue_id)
+        WHERE $1::UUID IS NULL OR i.newsletter_issue_id = $1
+        GROUP BY i.newsletter_issue_id
+        ORDER BY i.published_at DESC
+        "#,
+        id
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+async fn list_deliveries(db_pool: &PgPool, id: Uuid) -> Result<Vec<Delivery>, sqlx::Error> {
+    sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT subscriber_email, status, attempts, last_error
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1
+        ORDER BY subscriber_email
+        "#,
+        id
+    )
+    .fetch_all(db_pool)
+    .await
+}
@@ -0,0 +1,67 @@
+use anyhow::Context;
+use clap::Subcommand;
+
+use super::output::{print_message, print_records, OutputFormat, Tabular};
+use crate::{
+    configuration::Settings,
+    migrations::{migration_status, revert_last_migration, run_migrations, MigrationStatus},
+    startup::get_connection_pool,
+};
+
+#[derive(Subcommand)]
+pub enum MigrateCommand {
+    /// Apply every pending migration.
+    Up,
+    /// List the embedded migrations and whether they have been applied.
+    Status,
+    /// Revert the most recently applied migration.
+    Revert,
+}
+
+impl Tabular for MigrationStatus {
+    fn headers() -> &'static [&'static str] {
+        &["VERSION", "STATE", "DESCRIPTION"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.version.to_string(),
+            self.state.as_str().to_string(),
+            self.description.clone(),
+        ]
+    }
+}
+
+pub async fn run(
+    command: MigrateCommand,
+    configuration: &Settings,
+    format: OutputFormat,
+) -> Result<(), anyhow::Error> {
+    let db_pool = get_connection_pool(&configuration.database);
+
+    match command {
+        MigrateCommand::Up => {
+            run_migrations(&db_pool)
+                .await
+                .context("Failed to apply database migrations.")?;
+            print_message(format, "Database is up to date.");
+        }
+        MigrateCommand::Status => {
+            let status = migration_status(&db_pool)
+                .await
+                .context("Failed to retrieve migration status.")?;
+            print_records(format, &status)?;
+        }
+        MigrateCommand::Revert => {
+            match revert_last_migration(&db_pool)
+                .await
+                .context("Failed to revert the last migration.")?
+            {
+                Some(version) => print_message(format, &format!("Reverted migration {version}.")),
+                None => print_message(format, "No migration has been applied yet."),
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use clap::{Parser, Subcommand};
+
+pub mod config;
+pub mod issues;
+pub mod migrate;
+pub mod output;
+pub mod subscribers;
+pub mod tokens;
+
+pub use config::ConfigCommand;
+pub use issues::IssuesCommand;
+pub use migrate::MigrateCommand;
+pub use output::OutputFormat;
+pub use subscribers::SubscribersCommand;
+pub use tokens::TokensCommand;
+
+use anyhow::Context;
+
+use crate::{
+    configuration::{configuration_directory, get_environment, Settings, SettingsReloader},
+    digest::enqueue_digests_until_stopped,
+    email_outbox::relay_until_stopped,
+    startup::{get_connection_pool, Application},
+    subscription_tokens::TokenHasher,
+    telemetry::{LogFilter, LogFilterHandle},
+};
+
+/// Command line interface of the `zero2prod` binary.
+#[derive(Parser)]
+#[command(name = "zero2prod", version, about = "Newsletter delivery service")]
+pub struct Cli {
+    /// Print results as JSON instead of human-readable tables.
+    #[arg(long, global = true)]
+    pub json: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    pub fn output_format(&self) -> OutputFormat {
+        if self.json {
+            OutputFormat::Json
+        } else {
+            OutputFormat::Table
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the HTTP server. This is the default when no command is given.
+    Serve,
+    /// Inspect and apply the embedded database migrations.
+    Migrate {
+        #[command(subcommand)]
+        command: MigrateCommand,
+    },
+    /// Manage subscribers.
+    Subscribers {
+        #[command(subcommand)]
+        command: SubscribersCommand,
+    },
+    /// Inspect and deliver newsletter issues.
+    Issues {
+        #[command(subcommand)]
+        command: IssuesCommand,
+    },
+    /// Manage subscription tokens.
+    Tokens {
+        #[command(subcommand)]
+        command: TokensCommand,
+    },
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+pub async fn run(
+    command: Command,
+    configuration: Settings,
+    format: OutputFormat,
+    log_filter: LogFilterHandle,
+) -> Result<(), anyhow::Error> {
+    match command {
+        Command::Serve => {
+            let log_filter = LogFilter::new(log_filter);
+            log_filter
+                .configure(configuration.application.log_filter.as_deref())
+                .map_err(anyhow::Error::msg)?;
+            let db_pool = get_connection_pool(&configuration.database);
+            let email_outbox = configuration.email_outbox.clone();
+            let digest = enqueue_digests_until_stopped(
+                db_pool.clone(),
+                configuration.application.base_url.clone(),
+                TokenHasher::from_settings(&configuration.confirmation)?,
+                configuration.digest.clone(),
+            );
+            let application = Application::build(configuration, log_filter.clone()).await?;
+            let reloader = SettingsReloader::new(
+                configuration_directory()?,
+                get_environment()?,
+                application.runtime_settings(),
+                Some(log_filter),
+            )
+            .context("Failed to watch the configuration for changes.")?;
+            tokio::spawn(reloader.watch());
+            tokio::spawn(relay_until_stopped(
+                db_pool,
+                application.runtime_settings(),
+                email_outbox,
+            ));
+            tokio::spawn(digest);
+            application.run_until_stopped().await?;
+            Ok(())
+        }
+        Command::Migrate { command } => migrate::run(command, &configuration, format).await,
+        Command::Subscribers { command } => subscribers::run(command, &configuration, format).await,
+        Command::Issues { command } => issues::run(command, &configuration, format).await,
+        Command::Tokens { command } => tokens::run(command, &configuration, format).await,
+        Command::Config { command } => config::run(command, format).await,
+    }
+}
@@ -0,0 +1,79 @@
+use serde::Serialize;
+
+/// How command results are written to stdout.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// A record that can be rendered as a row of a table.
+pub trait Tabular {
+    fn headers() -> &'static [&'static str];
+    fn row(&self) -> Vec<String>;
+}
+
+/// Print a list of records, either as an aligned table or as a JSON array.
+pub fn print_records<T: Tabular + Serialize>(
+    format: OutputFormat,
+    records: &[T],
+) -> Result<(), anyhow::Error> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(records)?),
+        OutputFormat::Table => {
+            let rows: Vec<_> = records.iter().map(Tabular::row).collect();
+            print_table(T::headers(), &rows);
+        }
+    }
+    Ok(())
+}
+
+/// Print a single record, either as `KEY  value` lines or as a JSON object.
+pub fn print_record<T: Tabular + Serialize>(
+    format: OutputFormat,
+    record: &T,
+) -> Result<(), anyhow::Error> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(record)?),
+        OutputFormat::Table => {
+            let width = T::headers().iter().map(|h| h.len()).max().unwrap_or(0);
+            for (header, value) in T::headers().iter().zip(record.row()) {
+                println!("{header:<width$}  {value}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print a one-line outcome, wrapped in `{"message": ...}` for JSON output.
+pub fn print_message(format: OutputFormat, message: &str) {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::json!({ "message": message })),
+        OutputFormat::Table => println!("{message}"),
+    }
+}
+
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!("{}", format_row(headers.to_vec()));
+    for row in rows {
+        println!("{}", format_row(row.iter().map(String::as_str).collect()));
+    }
+}
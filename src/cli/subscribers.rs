@@ -0,0 +1,400 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::Subcommand;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::output::{print_message, print_records, OutputFormat, Tabular};
+use crate::{
+    configuration::Settings,
+    domain::{NewSubscriber, SubscriberEmail},
+    email_outbox::relay_pending_emails,
+    routes::{
+        confirm_subscriber, enqueue_confirmation_email, insert_subscriber, store_token, FormData,
+    },
+    startup::get_connection_pool,
+    subscription_tokens::{generate_subscription_token, TokenHasher},
+};
+
+#[derive(Subcommand)]
+pub enum SubscribersCommand {
+    /// List subscribers, optionally filtered by status.
+    List {
+        /// Only list subscribers with this status, e.g. `confirmed`.
+        #[arg(long)]
+        status: Option<String>,
+    },
+    /// Add a subscriber and send them a confirmation email.
+    Add {
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        email: String,
+        /// Confirm the subscriber straight away instead of emailing them.
+        #[arg(long)]
+        confirmed: bool,
+        /// Where they signed up, for use in segments.
+        #[arg(long)]
+        source: Option<String>,
+    },
+    /// Confirm a pending subscriber.
+    Confirm {
+        /// Email address or id of the subscriber.
+        subscriber: String,
+    },
+    /// Remove a subscriber together with their confirmation tokens.
+    Remove {
+        /// Email address or id of the subscriber.
+        subscriber: String,
+    },
+    /// Tag a subscriber, for use in segments.
+    Tag {
+        /// Email address or id of the subscriber.
+        subscriber: String,
+        #[arg(required = true, value_parser = parse_name)]
+        tags: Vec<String>,
+    },
+    /// Remove tags from a subscriber.
+    Untag {
+        /// Email address or id of the subscriber.
+        subscriber: String,
+        #[arg(required = true, value_parser = parse_name)]
+        tags: Vec<String>,
+    },
+    /// Set custom attributes of a subscriber, for use in segments. An empty
+    /// value removes the attribute.
+    SetAttributes {
+        /// Email address or id of the subscriber.
+        subscriber: String,
+        /// `key=value` pairs, e.g. `plan=pro`.
+        #[arg(required = true, value_parser = parse_attribute)]
+        attributes: Vec<(String, String)>,
+    },
+    /// List subscribers whose email addresses are the same once case and
+    /// surrounding whitespace are ignored. They have to be merged before the
+    /// case-insensitive unique index can be created.
+    Duplicates,
+}
+
+#[derive(Serialize)]
+struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+impl Tabular for Subscriber {
+    fn headers() -> &'static [&'static str] {
+        &["ID", "EMAIL", "NAME", "STATUS", "SUBSCRIBED AT"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.email.clone(),
+            self.name.clone(),
+            self.status.clone(),
+            self.subscribed_at.to_rfc3339(),
+        ]
+    }
+}
+
+pub async fn run(
+    command: SubscribersCommand,
+    configuration: &Settings,
+    format: OutputFormat,
+) -> Result<(), anyhow::Error> {
+    let db_pool = get_connection_pool(&configuration.database);
+
+    match command {
+        SubscribersCommand::List { status } => {
+            let subscribers = list_subscribers(&db_pool, status.as_deref())
+                .await
+                .context("Failed to list subscribers.")?;
+            print_records(format, &subscribers)?;
+        }
+        SubscribersCommand::Add {
+            name,
+            email,
+            confirmed,
+            source,
+        } => {
+            add_subscriber(
+                &db_pool,
+                configuration,
+                FormData {
+                    name,
+                    email,
+                    source,
+                    bot_check: Default::default(),
+                },
+                confirmed,
+            )
+            .await?;
+            print_message(format, "Subscriber added.");
+        }
+        SubscribersCommand::Confirm { subscriber } => {
+            let subscriber_id = find_subscriber_id(&db_pool, &subscriber).await?;
+            confirm_subscriber(&db_pool, subscriber_id)
+                .await
+                .context("Failed to confirm subscription in database.")?;
+            print_message(format, &format!("Confirmed {subscriber}."));
+        }
+        SubscribersCommand::Remove { subscriber } => {
+            let subscriber_id = find_subscriber_id(&db_pool, &subscriber).await?;
+            remove_subscriber(&db_pool, subscriber_id)
+                .await
+                .context("Failed to remove subscriber from database.")?;
+            print_message(format, &format!("Removed {subscriber}."));
+        }
+        SubscribersCommand::Tag { subscriber, tags } => {
+            let subscriber_id = find_subscriber_id(&db_pool, &subscriber).await?;
+            sqlx::query!(
+                r#"
+                INSERT INTO subscriber_tags (subscriber_id, tag)
+                SELECT $1, tag FROM UNNEST($2::text[]) AS tag
+                ON CONFLICT DO NOTHING
+                "#,
+                subscriber_id,
+                &tags
+            )
+            .execute(&db_pool)
+            .await
+            .context("Failed to tag subscriber.")?;
+            print_message(format, &format!("Tagged {subscriber}."));
+        }
+        SubscribersCommand::Untag { subscriber, tags } => {
+            let subscriber_id = find_subscriber_id(&db_pool, &subscriber).await?;
+            sqlx::query!(
+                "DELETE FROM subscriber_tags WHERE subscriber_id = $1 AND tag = ANY($2)",
+                subscriber_id,
+                &tags
+            )
+            .execute(&db_pool)
+            .await
+            .context("Failed to untag subscriber.")?;
+            print_message(format, &format!("Untagged {subscriber}."));
+        }
+        SubscribersCommand::SetAttributes {
+            subscriber,
+            attributes,
+        } => {
+            let subscriber_id = find_subscriber_id(&db_pool, &subscriber).await?;
+            set_attributes(&db_pool, subscriber_id, &attributes)
+                .await
+                .context("Failed to set subscriber attributes.")?;
+            print_message(format, &format!("Updated the attributes of {subscriber}."));
+        }
+        SubscribersCommand::Duplicates => {
+            let duplicates = list_duplicate_subscribers(&db_pool)
+                .await
+                .context("Failed to list duplicate subscribers.")?;
+            print_records(format, &duplicates)?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn add_subscriber(
+    db_pool: &PgPool,
+    configuration: &Settings,
+    form: FormData,
+    confirmed: bool,
+) -> Result<(), anyhow::Error> {
+    let new_subscriber: NewSubscriber = form.try_into()?;
+    let mut tx = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let subscriber_id = insert_subscriber(&mut tx, &new_subscriber)
+        .await
+        .context("Failed to insert new subscriber into the database.")?
+        .context("This address is already subscribed.")?;
+
+    if confirmed {
+        confirm_subscriber(&mut *tx, subscriber_id)
+            .await
+            .context("Failed to confirm subscription in database.")?;
+        tx.commit()
+            .await
+            .context("Failed to commit SQL transaction to store a new subscriber.")?;
+        return Ok(());
+    }
+
+    let token_hasher = TokenHasher::from_settings(&configuration.confirmation)?;
+    let subscription_token = generate_subscription_token();
+    store_token(&mut tx, &token_hasher, subscriber_id, &subscription_token)
+        .await
+        .context("Failed to store confirmation token for a new subscriber.")?;
+    enqueue_confirmation_email(
+        &mut tx,
+        &new_subscriber.email,
+        &configuration.application.base_url,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to add the confirmation email to the outbox.")?;
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new subscriber.")?;
+
+    // Send it right away rather than wait for a server to relay it.
+    let email_client = configuration
+        .email_client
+        .clone()
+        .client()
+        .map_err(anyhow::Error::msg)?;
+    relay_pending_emails(db_pool, &email_client, &configuration.email_outbox)
+        .await
+        .context("Failed to relay the email outbox.")?;
+
+    Ok(())
+}
+
+/// Tags and attribute keys appear bare in segments, e.g. `attr.plan = pro`.
+fn parse_name(name: &str) -> Result<String, String> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "`{name}` may only contain letters, digits, `-` and `_`"
+        ));
+    }
+    Ok(name)
+}
+
+fn parse_attribute(attribute: &str) -> Result<(String, String), String> {
+    let (key, value) = attribute
+        .split_once('=')
+        .ok_or_else(|| format!("`{attribute}` is not a `key=value` pair"))?;
+    Ok((parse_name(key)?, value.trim().to_string()))
+}
+
+async fn set_attributes(
+    db_pool: &PgPool,
+    subscriber_id: Uuid,
+    attributes: &[(String, String)],
+) -> Result<(), sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+    for (key, value) in attributes {
+        if value.is_empty() {
+            sqlx::query!(
+                "DELETE FROM subscriber_attributes WHERE subscriber_id = $1 AND key = $2",
+                subscriber_id,
+                key
+            )
+            .execute(&mut *tx)
+            .await?;
+        } else {
+            sqlx::query!(
+                r#"
+                INSERT INTO subscriber_attributes (subscriber_id, key, value)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (subscriber_id, key) DO UPDATE SET value = EXCLUDED.value
+                "#,
+                subscriber_id,
+                key,
+                value
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+    tx.commit().await
+}
+
+async fn list_subscribers(
+    db_pool: &PgPool,
+    status: Option<&str>,
+) -> Result<Vec<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, display_email AS email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE $1::TEXT IS NULL OR status = $1
+        ORDER BY subscribed_at
+        "#,
+        status
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+/// Only reads columns that predate canonical emails, so that duplicates can
+/// be listed while the migration enforcing uniqueness is pending.
+async fn list_duplicate_subscribers(db_pool: &PgPool) -> Result<Vec<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE lower(btrim(email)) IN (
+            SELECT lower(btrim(email))
+            FROM subscriptions
+            GROUP BY lower(btrim(email))
+            HAVING COUNT(*) > 1
+        )
+        ORDER BY lower(btrim(email)), subscribed_at
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+/// Look a subscriber up by id or, ignoring case, by email.
+async fn find_subscriber_id(db_pool: &PgPool, subscriber: &str) -> Result<Uuid, anyhow::Error> {
+    if let Ok(id) = Uuid::parse_str(subscriber) {
+        return sqlx::query!("SELECT id FROM subscriptions WHERE id = $1", id)
+            .fetch_optional(db_pool)
+            .await
+            .context("Failed to look up subscriber.")?
+            .map(|r| r.id)
+            .with_context(|| format!("There is no subscriber with id {id}."));
+    }
+
+    let email = SubscriberEmail::parse(subscriber.to_string())
+        .map(|email| email.as_ref().to_string())
+        .unwrap_or_else(|_| subscriber.to_string());
+    let ids: Vec<Uuid> = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE lower(email) = lower($1)",
+        email
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to look up subscriber.")?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+    match ids.as_slice() {
+        [] => anyhow::bail!("There is no subscriber with email {subscriber}."),
+        [id] => Ok(*id),
+        _ => {
+            anyhow::bail!(
+            "Several subscribers have the email {subscriber}, use one of their ids instead: {}.",
+            ids.iter().map(Uuid::to_string).collect::<Vec<_>>().join(", ")
+        )
+        }
+    }
+}
+
+async fn remove_subscriber(db_pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
@@ -0,0 +1,45 @@
+use anyhow::Context;
+use clap::Subcommand;
+use sqlx::PgPool;
+
+use super::output::{print_message, OutputFormat};
+use crate::{configuration::Settings, startup::get_connection_pool};
+
+#[derive(Subcommand)]
+pub enum TokensCommand {
+    /// Delete the confirmation tokens of subscribers who are already confirmed.
+    Purge,
+}
+
+pub async fn run(
+    command: TokensCommand,
+    configuration: &Settings,
+    format: OutputFormat,
+) -> Result<(), anyhow::Error> {
+    let db_pool = get_connection_pool(&configuration.database);
+
+    match command {
+        TokensCommand::Purge => {
+            let purged = purge_tokens(&db_pool)
+                .await
+                .context("Failed to purge subscription tokens.")?;
+            print_message(format, &format!("Purged {purged} token(s)."));
+        }
+    }
+
+    Ok(())
+}
+
+async fn purge_tokens(db_pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens t
+        USING subscriptions s
+        WHERE t.subscriber_id = s.id AND s.status = 'confirmed'
+        "#
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,817 @@
+mod reload;
+mod secrets;
+mod validation;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use ipnet::IpNet;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
+use sqlx::postgres::PgConnectOptions;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::postgres::PgSslMode;
+
+use crate::{
+    domain::{
+        normalize_domain, parse_domain_list, EmailPolicy, SubscriberEmail, UnknownTopicError,
+    },
+    email_client::EmailClient,
+};
+
+pub use reload::{ReloadReport, RuntimeSettings, SettingsReloader, SharedRuntimeSettings};
+pub use secrets::{
+    EnvSecretProvider, FileSecretProvider, SecretProvider, SecretSource, SECRET_KEYS,
+};
+pub use validation::InvalidConfiguration;
+
+#[derive(Deserialize, Clone)]
+pub struct Settings {
+    pub database: DatabaseSettings,
+    pub application: ApplicationSettings,
+    pub email_client: EmailClientSettings,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    #[serde(default)]
+    pub bot_protection: BotProtectionSettings,
+    #[serde(default)]
+    pub email_policy: EmailPolicySettings,
+    #[serde(default)]
+    pub deliverability: DeliverabilitySettings,
+    #[serde(default)]
+    pub confirmation: ConfirmationSettings,
+    #[serde(default)]
+    pub email_outbox: EmailOutboxSettings,
+    #[serde(default)]
+    pub digest: DigestSettings,
+    #[serde(default)]
+    pub archive: ArchiveSettings,
+    /// What issues can be about. Subscribers pick the ones they want in their
+    /// preferences.
+    #[serde(default)]
+    pub topics: Vec<Topic>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ApplicationSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub host: String,
+    pub base_url: String,
+    /// Overrides `RUST_LOG` for the server, e.g. `info,sqlx=warn`. Reloadable.
+    #[serde(default)]
+    pub log_filter: Option<String>,
+    /// Bearer token for the `/admin` endpoints, which are disabled if unset.
+    #[serde(default)]
+    pub admin_token: Option<SecretString>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct EmailClientSettings {
+    pub base_url: String,
+    pub sender_email: String,
+    pub authorization_token: SecretString,
+    pub timeout_milliseconds: u64,
+}
+
+impl EmailClientSettings {
+    pub fn client(self) -> Result<EmailClient, String> {
+        let sender_email = self.sender()?;
+        let timeout = self.timeout();
+        EmailClient::new(
+            self.base_url,
+            sender_email,
+            self.authorization_token,
+            timeout,
+        )
+    }
+
+    /// The sender is only checked for syntax: the [`EmailPolicy`] applies to
+    /// subscribers, and `noreply@` is a perfectly good sender.
+    pub fn sender(&self) -> Result<SubscriberEmail, String> {
+        SubscriberEmail::parse(self.sender_email.clone()).map_err(|e| e.to_string())
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct RateLimitSettings {
+    /// Where token buckets are kept. Use `postgres` to share them between instances.
+    #[serde(default)]
+    pub store: RateLimitStoreKind,
+    /// Proxies allowed to report the client address in `X-Forwarded-For`,
+    /// as addresses or networks, e.g. `10.0.0.0/8`.
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    pub trusted_proxies: Vec<IpNet>,
+    /// Limits keyed by route name, e.g. `subscribe`.
+    #[serde(default)]
+    pub routes: HashMap<String, RouteRateLimits>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitStoreKind {
+    #[default]
+    Memory,
+    Postgres,
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct RouteRateLimits {
+    pub per_ip: Option<RateLimit>,
+    pub per_email: Option<RateLimit>,
+}
+
+/// Allow `requests` every `per_seconds`, in bursts of up to `requests`.
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct RateLimit {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub requests: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub per_seconds: u64,
+}
+
+fn deserialize_networks<'de, D>(deserializer: D) -> Result<Vec<IpNet>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|network| {
+            network
+                .parse::<IpNet>()
+                .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| {
+                    D::Error::custom(format!("`{network}` is not an IP address or network"))
+                })
+        })
+        .collect()
+}
+
+/// Which addresses may subscribe, see [`EmailPolicy`].
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct EmailPolicySettings {
+    /// Reject addresses at known disposable email providers.
+    pub reject_disposable: bool,
+    /// More disposable domains, one per line, on top of the bundled list.
+    pub disposable_domains_file: Option<PathBuf>,
+    /// Reject role addresses such as `noreply@` or `postmaster@`.
+    pub reject_role_addresses: bool,
+    /// Domains exempt from the disposable and denied lists.
+    pub allowed_domains: Vec<String>,
+    pub denied_domains: Vec<String>,
+}
+
+impl Default for EmailPolicySettings {
+    fn default() -> Self {
+        Self {
+            reject_disposable: true,
+            disposable_domains_file: None,
+            reject_role_addresses: false,
+            allowed_domains: Vec::new(),
+            denied_domains: Vec::new(),
+        }
+    }
+}
+
+impl EmailPolicySettings {
+    pub fn policy(&self) -> Result<EmailPolicy, String> {
+        let mut disposable_domains = HashSet::new();
+        if self.reject_disposable {
+            disposable_domains.extend(EmailPolicy::bundled_disposable_domains());
+            if let Some(path) = &self.disposable_domains_file {
+                let list = std::fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read `{}`: {e}", path.display()))?;
+                disposable_domains.extend(parse_domain_list(&list));
+            }
+        }
+        Ok(EmailPolicy {
+            disposable_domains,
+            reject_role_addresses: self.reject_role_addresses,
+            allowed_domains: self
+                .allowed_domains
+                .iter()
+                .map(|d| normalize_domain(d))
+                .collect(),
+            denied_domains: self
+                .denied_domains
+                .iter()
+                .map(|d| normalize_domain(d))
+                .collect(),
+        })
+    }
+}
+
+/// Checks that the domain of new subscribers can receive email, see
+/// [`DeliverabilityCheck`](crate::deliverability::DeliverabilityCheck).
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct DeliverabilitySettings {
+    /// Resolve MX records, falling back to A/AAAA, for every new subscriber.
+    pub enabled: bool,
+    /// Lookups taking longer than this let the address through.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+    /// How long a lookup result is reused for.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cache_ttl_seconds: u64,
+}
+
+impl Default for DeliverabilitySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout_milliseconds: 2000,
+            cache_ttl_seconds: 60 * 60,
+        }
+    }
+}
+
+/// Settings for the worker relaying the email outbox, see
+/// [`email_outbox`](crate::email_outbox).
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct EmailOutboxSettings {
+    /// How often the worker looks for emails to send.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub poll_interval_milliseconds: u64,
+    /// Emails still failing after this many attempts are given up on.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: u32,
+    /// The wait before the first retry, doubled after every failure.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub retry_delay_seconds: u64,
+}
+
+impl EmailOutboxSettings {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_milliseconds)
+    }
+}
+
+impl Default for EmailOutboxSettings {
+    fn default() -> Self {
+        Self {
+            poll_interval_milliseconds: 1000,
+            max_attempts: 8,
+            retry_delay_seconds: 10,
+        }
+    }
+}
+
+/// Something issues can be about, e.g. `id: releases`, `name: Release notes`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Topic {
+    /// What issues and preferences refer to the topic by. Renaming it drops
+    /// the topic from the preferences that picked it.
+    pub id: String,
+    /// What subscribers see.
+    pub name: String,
+}
+
+/// The configured topics.
+pub struct Topics(pub Vec<Topic>);
+
+impl Topics {
+    pub fn iter(&self) -> impl Iterator<Item = &Topic> {
+        self.0.iter()
+    }
+
+    /// Fails unless `id` is one of the configured topics.
+    pub fn check(&self, id: &str) -> Result<(), UnknownTopicError> {
+        if self.0.iter().any(|topic| topic.id == id) {
+            Ok(())
+        } else {
+            Err(UnknownTopicError(id.to_string()))
+        }
+    }
+}
+
+/// Settings for the weekly digest, see [`digest`](crate::digest).
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct DigestSettings {
+    /// How long subscribers wait between two digests.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub interval_hours: u64,
+    /// How often the worker looks for digests that are due.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub poll_interval_seconds: u64,
+}
+
+impl DigestSettings {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_hours * 60 * 60)
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval_seconds)
+    }
+}
+
+impl Default for DigestSettings {
+    fn default() -> Self {
+        Self {
+            interval_hours: 7 * 24,
+            poll_interval_seconds: 60,
+        }
+    }
+}
+
+/// Settings for the public `/archive` of issues and its feeds.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ArchiveSettings {
+    /// The name of the newsletter, as feed readers show it.
+    pub title: String,
+    pub description: String,
+    /// How many of the latest public issues the feeds include.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub feed_length: i64,
+}
+
+impl Default for ArchiveSettings {
+    fn default() -> Self {
+        Self {
+            title: "Newsletter".into(),
+            description: String::new(),
+            feed_length: 20,
+        }
+    }
+}
+
+/// Settings for the links in confirmation emails.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ConfirmationSettings {
+    /// Links older than this show the "expired" page instead of confirming.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub token_ttl_hours: u64,
+    /// Key of the HMAC that tokens are stored as and preferences links are
+    /// signed with. Changing it invalidates every pending confirmation link
+    /// and every preferences link in emails already sent.
+    pub token_key: Option<SecretString>,
+    /// Pages hosted elsewhere to send subscribers to instead of ours.
+    pub redirects: ConfirmationRedirects,
+}
+
+impl ConfirmationSettings {
+    pub fn token_ttl(&self) -> Duration {
+        Duration::from_secs(self.token_ttl_hours * 60 * 60)
+    }
+}
+
+impl Default for ConfirmationSettings {
+    fn default() -> Self {
+        Self {
+            token_ttl_hours: 72,
+            token_key: None,
+            redirects: ConfirmationRedirects::default(),
+        }
+    }
+}
+
+/// Where to redirect subscribers for each outcome of a confirmation link.
+/// Outcomes without a URL render our own page.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ConfirmationRedirects {
+    pub confirmed: Option<String>,
+    pub already_confirmed: Option<String>,
+    pub expired: Option<String>,
+    pub invalid: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BotProtectionSettings {
+    /// Check subscriptions for bots. Suspected bots get a fake success.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Signs the timestamp embedded in the subscription form.
+    #[serde(default)]
+    pub signing_key: Option<SecretString>,
+    /// Submissions sooner than this after the form was rendered are bots.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_submit_seconds: u64,
+    /// Forms older than this are rejected, so tokens cannot be reused forever.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_form_age_seconds: u64,
+    /// Also require a CAPTCHA, if set.
+    #[serde(default)]
+    pub captcha: Option<CaptchaSettings>,
+}
+
+impl Default for BotProtectionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            signing_key: None,
+            min_submit_seconds: 3,
+            max_form_age_seconds: 24 * 60 * 60,
+            captcha: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CaptchaSettings {
+    pub provider: CaptchaProvider,
+    /// Public key embedded in the form widget.
+    pub site_key: String,
+    pub secret: SecretString,
+    /// Overrides the provider's verification endpoint.
+    #[serde(default)]
+    pub verify_url: Option<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptchaProvider {
+    Hcaptcha,
+    Turnstile,
+}
+
+impl CaptchaProvider {
+    pub fn verify_url(&self) -> &'static str {
+        match self {
+            CaptchaProvider::Hcaptcha => "https://api.hcaptcha.com/siteverify",
+            CaptchaProvider::Turnstile => {
+                "https://challenges.cloudflare.com/turnstile/v0/siteverify"
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct DatabaseSettings {
+    /// Required unless `database_url` is set.
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<SecretString>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    /// Required unless `database_url` is set.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Required unless `database_url` is set.
+    #[serde(default)]
+    pub database_name: Option<String>,
+    pub require_ssl: bool,
+    /// A `postgres://` connection string, instead of `host`, `port`,
+    /// `username`, `password` and `database_name`.
+    #[serde(default)]
+    pub database_url: Option<DatabaseUrl>,
+    /// Apply pending migrations when the application starts.
+    #[serde(default)]
+    pub auto_migrate: bool,
+    /// Reported to Postgres, e.g. in `pg_stat_activity`.
+    pub application_name: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_connections: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_connections: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub acquire_timeout_milliseconds: u64,
+    /// Close connections that have been idle for longer. Never, if unset.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub idle_timeout_seconds: Option<u64>,
+    /// Close connections that have been open for longer. Never, if unset.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub max_lifetime_seconds: Option<u64>,
+    /// Abort any statement that takes longer. No limit, if unset.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub statement_timeout_milliseconds: Option<u64>,
+}
+
+impl DatabaseSettings {
+    pub fn connect_options(&self) -> PgConnectOptions {
+        let options = match &self.database_url {
+            Some(url) => url.0.clone(),
+            None => {
+                let mut options = PgConnectOptions::new()
+                    .port(self.port)
+                    .ssl_mode(PgSslMode::Prefer);
+                if let Some(host) = &self.host {
+                    options = options.host(host);
+                }
+                if let Some(username) = &self.username {
+                    options = options.username(username);
+                }
+                if let Some(password) = &self.password {
+                    options = options.password(password.expose_secret());
+                }
+                if let Some(database_name) = &self.database_name {
+                    options = options.database(database_name);
+                }
+                options
+            }
+        };
+
+        let options = if self.require_ssl {
+            options.ssl_mode(PgSslMode::Require)
+        } else {
+            options
+        };
+
+        let options = options.application_name(&self.application_name);
+        match self.statement_timeout_milliseconds {
+            Some(timeout) => options.options([("statement_timeout", timeout.to_string())]),
+            None => options,
+        }
+    }
+
+    /// Where the settings connect to, as `host:port/database`, without any
+    /// credentials.
+    pub fn target(&self) -> String {
+        let options = self.connect_options();
+        format!(
+            "{}:{}/{}",
+            options.get_host(),
+            options.get_port(),
+            options.get_database().unwrap_or_default()
+        )
+    }
+
+    pub fn pool_options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.acquire_timeout())
+            .idle_timeout(self.idle_timeout_seconds.map(Duration::from_secs))
+            .max_lifetime(self.max_lifetime_seconds.map(Duration::from_secs))
+    }
+
+    pub fn acquire_timeout(&self) -> Duration {
+        Duration::from_millis(self.acquire_timeout_milliseconds)
+    }
+}
+
+/// A Postgres connection string, parsed when the configuration is loaded.
+#[derive(Clone)]
+pub struct DatabaseUrl(PgConnectOptions);
+
+impl<'de> Deserialize<'de> for DatabaseUrl {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let url = SecretString::deserialize(deserializer)?;
+        // Do not echo the URL back: it usually embeds the password.
+        PgConnectOptions::from_str(url.expose_secret())
+            .map(Self)
+            .map_err(|_| D::Error::custom("database_url is not a valid Postgres connection string"))
+    }
+}
+
+/// Why the configuration could not be loaded.
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigurationError {
+    #[error("Failed to load the configuration: {0}")]
+    Load(#[from] config::ConfigError),
+    #[error("Failed to parse APP_ENVIRONMENT: {0}")]
+    Environment(String),
+    #[error(transparent)]
+    Invalid(#[from] InvalidConfiguration),
+}
+
+pub fn get_configuration() -> Result<Settings, ConfigurationError> {
+    let (settings, environment) = get_unvalidated_configuration()?;
+    settings.validate(&environment)?;
+    Ok(settings)
+}
+
+/// The settings for the running environment, without checking them with
+/// [`Settings::validate`].
+pub fn get_unvalidated_configuration() -> Result<(Settings, Environment), ConfigurationError> {
+    let environment = get_environment()?;
+    let settings = load_configuration(&configuration_directory()?, &environment)?
+        .try_deserialize::<Settings>()?;
+    Ok((settings, environment))
+}
+
+/// Detect the running environment. Default to `local` if unspecified.
+pub fn get_environment() -> Result<Environment, ConfigurationError> {
+    std::env::var("APP_ENVIRONMENT")
+        .unwrap_or_else(|_| "local".into())
+        .try_into()
+        .map_err(ConfigurationError::Environment)
+}
+
+pub fn configuration_directory() -> Result<PathBuf, ConfigurationError> {
+    let base_path = std::env::current_dir().map_err(|e| {
+        config::ConfigError::Message(format!("Failed to determine the current directory: {e}"))
+    })?;
+    Ok(base_path.join("configuration"))
+}
+
+/// Merge every configuration layer for `environment`, later layers taking
+/// precedence:
+///
+/// 1. `base.yaml`
+/// 2. `{environment}.yaml`
+/// 3. `{environment}.local.yaml`, if present; meant for untracked overrides
+/// 4. secret files in `APP_SECRETS_DIRECTORY`, if set
+/// 5. `APP_*` environment variables, including `APP_*_FILE` secrets
+///
+/// Each value remembers the layer it came from, see [`config::Value::origin`].
+pub fn load_configuration(
+    configuration_directory: &Path,
+    environment: &Environment,
+) -> Result<config::Config, config::ConfigError> {
+    let environment_filename = format!("{}.yaml", environment.as_str());
+    let local_filename = format!("{}.local.yaml", environment.as_str());
+
+    // Initialize our configuration reader
+    let mut builder = config::Config::builder()
+        //Add configuration values.
+        .add_source(config::File::from(
+            configuration_directory.join("base.yaml"),
+        ))
+        .add_source(config::File::from(
+            configuration_directory.join(environment_filename),
+        ))
+        .add_source(
+            config::File::from(configuration_directory.join(local_filename)).required(false),
+        );
+
+    // Secrets mounted as files, e.g. Docker or Kubernetes secrets.
+    if let Ok(secrets_directory) = std::env::var("APP_SECRETS_DIRECTORY") {
+        builder = builder.add_source(SecretSource::new(FileSecretProvider::new(
+            secrets_directory,
+        )));
+    }
+
+    builder
+        .add_source(
+            config::Environment::with_prefix("APP")
+                .prefix_separator("_")
+                .separator("__"),
+        )
+        // Resolves `APP_<KEY>_FILE` indirection for secrets.
+        .add_source(SecretSource::new(EnvSecretProvider::new("APP")))
+        .build()
+}
+
+/// Every leaf value of a merged configuration, keyed by its dotted path, e.g.
+/// `database.port`.
+pub fn flatten(
+    configuration: config::Config,
+) -> Result<BTreeMap<String, config::Value>, config::ConfigError> {
+    use config::Source;
+
+    fn walk(prefix: &str, value: config::Value, values: &mut BTreeMap<String, config::Value>) {
+        match value.kind {
+            config::ValueKind::Table(table) => {
+                for (key, value) in table {
+                    walk(&join(prefix, &key), value, values);
+                }
+            }
+            config::ValueKind::Array(array) => {
+                for (i, value) in array.into_iter().enumerate() {
+                    walk(&join(prefix, &i.to_string()), value, values);
+                }
+            }
+            _ => {
+                values.insert(prefix.to_string(), value);
+            }
+        }
+    }
+
+    fn join(prefix: &str, key: &str) -> String {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{prefix}.{key}")
+        }
+    }
+
+    let mut values = BTreeMap::new();
+    for (key, value) in configuration.collect()? {
+        walk(&key, value, &mut values);
+    }
+    Ok(values)
+}
+
+/// The possible runtime environment for the application.
+///
+/// Besides the well-known environments, any other name selects a profile of
+/// its own, e.g. `APP_ENVIRONMENT=demo` layers `configuration/demo.yaml`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Environment {
+    Local,
+    Test,
+    Staging,
+    Production,
+    Named(String),
+}
+
+impl Environment {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Environment::Local => "local",
+            Environment::Test => "test",
+            Environment::Staging => "staging",
+            Environment::Production => "production",
+            Environment::Named(name) => name,
+        }
+    }
+}
+
+impl TryFrom<String> for Environment {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "local" => Ok(Self::Local),
+            "test" => Ok(Self::Test),
+            "staging" => Ok(Self::Staging),
+            "production" => Ok(Self::Production),
+            // The name becomes part of a file path: keep it to a plain file stem.
+            other
+                if !other.is_empty()
+                    && other
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+            {
+                Ok(Self::Named(other.to_string()))
+            }
+            other => Err(format!(
+                "`{other}` is not a valid environment name. Use `local`, `test`, `staging`, \
+                `production` or a name made of letters, digits, `-` and `_`."
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+    use uuid::Uuid;
+
+    use super::{load_configuration, Environment};
+
+    #[test]
+    fn well_known_environments_are_recognised() {
+        assert_ok_eq!(
+            Environment::try_from("Staging".to_string()),
+            Environment::Staging
+        );
+        assert_ok_eq!(Environment::try_from("test".to_string()), Environment::Test);
+    }
+
+    #[test]
+    fn other_names_select_a_named_profile() {
+        assert_ok_eq!(
+            Environment::try_from("demo-eu".to_string()),
+            Environment::Named("demo-eu".into())
+        );
+    }
+
+    #[test]
+    fn names_that_are_not_file_stems_are_rejected() {
+        assert_err!(Environment::try_from("".to_string()));
+        assert_err!(Environment::try_from("../secrets".to_string()));
+    }
+
+    #[test]
+    fn profile_layers_override_each_other_in_order() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("base.yaml"), "a: base\nb: base\nc: base").unwrap();
+        std::fs::write(directory.join("demo.yaml"), "b: demo\nc: demo").unwrap();
+        std::fs::write(directory.join("demo.local.yaml"), "c: demo.local").unwrap();
+
+        let settings = load_configuration(&directory, &Environment::Named("demo".into())).unwrap();
+
+        assert_eq!(settings.get_string("a").unwrap(), "base");
+        assert_eq!(settings.get_string("b").unwrap(), "demo");
+        assert_eq!(settings.get_string("c").unwrap(), "demo.local");
+    }
+
+    #[test]
+    fn the_local_override_file_is_optional() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("base.yaml"), "a: base").unwrap();
+        std::fs::write(directory.join("staging.yaml"), "a: staging").unwrap();
+
+        let settings = load_configuration(&directory, &Environment::Staging).unwrap();
+
+        assert_eq!(settings.get_string("a").unwrap(), "staging");
+    }
+}
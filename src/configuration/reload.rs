@@ -0,0 +1,325 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use super::{
+    flatten, load_configuration, ConfigurationError, Environment, RateLimitSettings, Settings,
+};
+use crate::{domain::EmailPolicy, email_client::EmailClient, telemetry::LogFilter};
+
+/// How often the configuration directory is checked for modified files.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Settings prefixes that take effect without a restart.
+const RELOADABLE_KEYS: &[&str] = &[
+    "email_client.",
+    "email_policy.",
+    "application.log_filter",
+    "rate_limit.routes.",
+    "rate_limit.trusted_proxies",
+];
+
+/// The part of the configuration that can change while the server is running.
+pub struct RuntimeSettings {
+    pub email_client: EmailClient,
+    pub email_policy: EmailPolicy,
+    pub rate_limit: RateLimitSettings,
+}
+
+impl RuntimeSettings {
+    pub fn new(settings: &Settings) -> Result<Self, ConfigurationError> {
+        let email_client = settings
+            .email_client
+            .clone()
+            .client()
+            .map_err(config::ConfigError::Message)?;
+        let email_policy = settings
+            .email_policy
+            .policy()
+            .map_err(config::ConfigError::Message)?;
+        Ok(Self {
+            email_client,
+            email_policy,
+            rate_limit: settings.rate_limit.clone(),
+        })
+    }
+}
+
+/// The current [`RuntimeSettings`], shared between request handlers and the
+/// [`SettingsReloader`].
+///
+/// Handlers take a snapshot with [`current`](Self::current) and keep using it
+/// for the rest of the request, so a reload never mixes old and new values.
+pub struct SharedRuntimeSettings(RwLock<Arc<RuntimeSettings>>);
+
+impl SharedRuntimeSettings {
+    pub fn new(settings: RuntimeSettings) -> Self {
+        Self(RwLock::new(Arc::new(settings)))
+    }
+
+    pub fn current(&self) -> Arc<RuntimeSettings> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, settings: RuntimeSettings) {
+        *self.0.write().unwrap() = Arc::new(settings);
+    }
+}
+
+/// What changed in a reload.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    /// Settings that now have their new value.
+    pub applied: Vec<String>,
+    /// Settings that changed but only take effect after a restart.
+    pub restart_required: Vec<String>,
+}
+
+/// Re-reads the configuration and swaps the reloadable subset into a
+/// [`SharedRuntimeSettings`].
+pub struct SettingsReloader {
+    directory: PathBuf,
+    environment: Environment,
+    shared: Arc<SharedRuntimeSettings>,
+    log_filter: Option<LogFilter>,
+    /// The value of every key as of the last reload, to detect changes.
+    values: BTreeMap<String, String>,
+}
+
+impl SettingsReloader {
+    pub fn new(
+        directory: PathBuf,
+        environment: Environment,
+        shared: Arc<SharedRuntimeSettings>,
+        log_filter: Option<LogFilter>,
+    ) -> Result<Self, ConfigurationError> {
+        let values = flatten(load_configuration(&directory, &environment)?)?
+            .into_iter()
+            .map(|(key, value)| (key, value.to_string()))
+            .collect();
+        Ok(Self {
+            directory,
+            environment,
+            shared,
+            log_filter,
+            values,
+        })
+    }
+
+    /// Load and validate the configuration, then apply what changed. Nothing
+    /// is applied if the new configuration is invalid.
+    pub fn reload(&mut self) -> Result<ReloadReport, ConfigurationError> {
+        let configuration = load_configuration(&self.directory, &self.environment)?;
+        let mut values: BTreeMap<_, _> = flatten(configuration.clone())?
+            .into_iter()
+            .map(|(key, value)| (key, value.to_string()))
+            .collect();
+        let settings = configuration.try_deserialize::<Settings>()?;
+        settings.validate(&self.environment)?;
+
+        let mut report = ReloadReport::default();
+        let mut keys: Vec<_> = self.values.keys().chain(values.keys()).cloned().collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            if self.values.get(&key) == values.get(&key) {
+                continue;
+            }
+            if RELOADABLE_KEYS.iter().any(|k| key.starts_with(k)) {
+                report.applied.push(key);
+            } else {
+                // Keep comparing against the value in use, so the change is
+                // reported until the application is restarted.
+                match self.values.get(&key) {
+                    Some(value) => values.insert(key.clone(), value.clone()),
+                    None => values.remove(&key),
+                };
+                report.restart_required.push(key);
+            }
+        }
+
+        if !report.applied.is_empty() {
+            let runtime_settings = RuntimeSettings::new(&settings)?;
+            if let Some(log_filter) = &self.log_filter {
+                log_filter
+                    .configure(settings.application.log_filter.as_deref())
+                    .map_err(config::ConfigError::Message)?;
+            }
+            self.shared.replace(runtime_settings);
+        }
+        self.values = values;
+        Ok(report)
+    }
+
+    /// Reload whenever a file in the configuration directory changes or the
+    /// process receives `SIGHUP`.
+    pub async fn watch(mut self) {
+        #[cfg(unix)]
+        let mut hangup =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).ok();
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        let mut modified = last_modified(&self.directory);
+
+        loop {
+            #[cfg(unix)]
+            let hangup = async {
+                match hangup.as_mut() {
+                    Some(signal) => signal.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let hangup = std::future::pending::<Option<()>>();
+
+            tokio::select! {
+                _ = hangup => {
+                    tracing::info!("Received SIGHUP, reloading the configuration.");
+                }
+                _ = interval.tick() => {
+                    let current = last_modified(&self.directory);
+                    if current == modified {
+                        continue;
+                    }
+                    modified = current;
+                    tracing::info!("The configuration changed on disk, reloading it.");
+                }
+            }
+
+            match self.reload() {
+                Ok(report) => {
+                    if !report.restart_required.is_empty() {
+                        tracing::warn!(
+                            settings = ?report.restart_required,
+                            "Some settings changed but only take effect after a restart."
+                        );
+                    }
+                    tracing::info!(settings = ?report.applied, "Reloaded the configuration.");
+                }
+                Err(e) => tracing::error!(
+                    error.message = %e,
+                    "Failed to reload the configuration, keeping the current settings."
+                ),
+            }
+        }
+    }
+}
+
+/// Modification time of every file in `directory`, to notice edits, new
+/// files and deletions alike.
+fn last_modified(directory: &PathBuf) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut files: Vec<_> = std::fs::read_dir(directory)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| {
+            let modified = entry.metadata().and_then(|m| m.modified()).ok();
+            (entry.path(), modified)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use claims::assert_err;
+    use uuid::Uuid;
+
+    use super::{RuntimeSettings, SettingsReloader, SharedRuntimeSettings};
+    use crate::configuration::{load_configuration, Environment, Settings};
+
+    const BASE: &str = r#"
+application:
+  port: 8000
+  host: 127.0.0.1
+  base_url: "http://127.0.0.1"
+database:
+  host: localhost
+  port: 5432
+  username: postgres
+  password: password
+  database_name: newsletter
+  require_ssl: false
+  application_name: zero2prod
+  max_connections: 10
+  min_connections: 0
+  acquire_timeout_milliseconds: 1000
+email_client:
+  base_url: "http://localhost"
+  sender_email: "test@example.com"
+  authorization_token: "token"
+  timeout_milliseconds: 1000
+confirmation:
+  token_key: "a-token-key-that-is-long-enough!"
+"#;
+
+    fn reloader(overrides: &str) -> (PathBuf, SettingsReloader, Arc<SharedRuntimeSettings>) {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("base.yaml"), BASE).unwrap();
+        std::fs::write(directory.join("test.yaml"), overrides).unwrap();
+
+        let settings: Settings = load_configuration(&directory, &Environment::Test)
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        let shared = Arc::new(SharedRuntimeSettings::new(
+            RuntimeSettings::new(&settings).unwrap(),
+        ));
+        let reloader =
+            SettingsReloader::new(directory.clone(), Environment::Test, shared.clone(), None)
+                .unwrap();
+        (directory, reloader, shared)
+    }
+
+    #[test]
+    fn email_settings_are_swapped_in() {
+        let (directory, mut reloader, shared) = reloader("");
+        let before = shared.current();
+        std::fs::write(
+            directory.join("test.yaml"),
+            "email_client:\n  sender_email: other@example.com",
+        )
+        .unwrap();
+
+        let report = reloader.reload().unwrap();
+
+        assert_eq!(report.applied, ["email_client.sender_email"]);
+        assert!(report.restart_required.is_empty());
+        assert!(!Arc::ptr_eq(&before, &shared.current()));
+    }
+
+    #[test]
+    fn settings_that_need_a_restart_are_reported_until_then() {
+        let (directory, mut reloader, shared) = reloader("");
+        let before = shared.current();
+        std::fs::write(directory.join("test.yaml"), "application:\n  port: 9000").unwrap();
+
+        let report = reloader.reload().unwrap();
+        assert_eq!(report.restart_required, ["application.port"]);
+        assert!(report.applied.is_empty());
+        assert!(Arc::ptr_eq(&before, &shared.current()));
+
+        let report = reloader.reload().unwrap();
+        assert_eq!(report.restart_required, ["application.port"]);
+    }
+
+    #[test]
+    fn invalid_changes_are_not_applied() {
+        let (directory, mut reloader, shared) = reloader("");
+        let before = shared.current();
+        std::fs::write(
+            directory.join("test.yaml"),
+            "email_client:\n  sender_email: not-an-email",
+        )
+        .unwrap();
+
+        assert_err!(reloader.reload());
+        assert!(Arc::ptr_eq(&before, &shared.current()));
+    }
+}
@@ -0,0 +1,227 @@
+use std::{fmt::Debug, path::PathBuf, sync::Arc};
+
+use config::{ConfigError, Map, Source, Value};
+use secrecy::{ExposeSecret, SecretString};
+
+/// Configuration keys that hold secrets and may be supplied by a [`SecretProvider`].
+pub const SECRET_KEYS: &[&str] = &[
+    "application.admin_token",
+    "bot_protection.signing_key",
+    "bot_protection.captcha.secret",
+    "confirmation.token_key",
+    "database.password",
+    "database.database_url",
+    "email_client.authorization_token",
+];
+
+/// Somewhere secrets can be looked up by their dotted configuration key,
+/// e.g. `database.password`.
+pub trait SecretProvider: Debug + Send + Sync {
+    fn secret(&self, key: &str) -> Result<Option<SecretString>, ConfigError>;
+}
+
+/// Reads each secret from a file named after its key inside a directory, which
+/// is how Docker and Kubernetes mount secrets (e.g. `/run/secrets/database.password`).
+#[derive(Debug)]
+pub struct FileSecretProvider {
+    directory: PathBuf,
+}
+
+impl FileSecretProvider {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+}
+
+impl SecretProvider for FileSecretProvider {
+    fn secret(&self, key: &str) -> Result<Option<SecretString>, ConfigError> {
+        let path = self.directory.join(key);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        read_secret_file(key, &path).map(Some)
+    }
+}
+
+/// Reads each secret from an environment variable, e.g. `APP_DATABASE__PASSWORD`
+/// for `database.password`.
+///
+/// If `APP_DATABASE__PASSWORD_FILE` is set instead, the secret is read from the
+/// file it points at.
+#[derive(Debug)]
+pub struct EnvSecretProvider {
+    prefix: String,
+}
+
+impl EnvSecretProvider {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+
+    fn variable_name(&self, key: &str) -> String {
+        format!("{}_{}", self.prefix, key.replace('.', "__").to_uppercase())
+    }
+}
+
+impl SecretProvider for EnvSecretProvider {
+    fn secret(&self, key: &str) -> Result<Option<SecretString>, ConfigError> {
+        let variable = self.variable_name(key);
+        if let Ok(path) = std::env::var(format!("{variable}_FILE")) {
+            return read_secret_file(key, &PathBuf::from(path)).map(Some);
+        }
+        Ok(std::env::var(variable).ok().map(SecretString::from))
+    }
+}
+
+/// Adapts a [`SecretProvider`] into a `config` source covering [`SECRET_KEYS`].
+#[derive(Debug, Clone)]
+pub struct SecretSource {
+    provider: Arc<dyn SecretProvider>,
+}
+
+impl SecretSource {
+    pub fn new(provider: impl SecretProvider + 'static) -> Self {
+        Self {
+            provider: Arc::new(provider),
+        }
+    }
+}
+
+impl Source for SecretSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<Map<String, Value>, ConfigError> {
+        let origin = format!("{:?}", self.provider);
+        let mut secrets = Map::new();
+        for key in SECRET_KEYS {
+            if let Some(secret) = self.provider.secret(key)? {
+                secrets.insert(
+                    key.to_string(),
+                    Value::new(Some(&origin), secret.expose_secret()),
+                );
+            }
+        }
+        Ok(secrets)
+    }
+}
+
+fn read_secret_file(key: &str, path: &PathBuf) -> Result<SecretString, ConfigError> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        ConfigError::Message(format!(
+            "Failed to read secret `{key}` from {}: {e}",
+            path.display()
+        ))
+    })?;
+    // Editors and `echo` usually leave a trailing newline behind.
+    Ok(contents.trim_end_matches(['\n', '\r']).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use claims::{assert_err, assert_none};
+    use secrecy::ExposeSecret;
+    use uuid::Uuid;
+
+    use super::{EnvSecretProvider, FileSecretProvider, SecretProvider, SecretSource};
+
+    fn secrets_directory() -> PathBuf {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn env_prefix() -> String {
+        format!("TEST_{}", Uuid::new_v4().simple())
+    }
+
+    #[test]
+    fn file_provider_reads_secret_without_trailing_newline() {
+        let directory = secrets_directory();
+        std::fs::write(directory.join("database.password"), "s3cret\n").unwrap();
+
+        let secret = FileSecretProvider::new(&directory)
+            .secret("database.password")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(secret.expose_secret(), "s3cret");
+    }
+
+    #[test]
+    fn file_provider_returns_none_for_missing_secrets() {
+        let provider = FileSecretProvider::new(secrets_directory());
+        assert_none!(provider.secret("database.password").unwrap());
+    }
+
+    #[test]
+    fn env_provider_reads_secret_from_variable() {
+        let prefix = env_prefix();
+        std::env::set_var(format!("{prefix}_DATABASE__PASSWORD"), "s3cret");
+
+        let secret = EnvSecretProvider::new(&prefix)
+            .secret("database.password")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(secret.expose_secret(), "s3cret");
+    }
+
+    #[test]
+    fn env_provider_follows_file_indirection() {
+        let prefix = env_prefix();
+        let path = secrets_directory().join("token");
+        std::fs::write(&path, "from-file").unwrap();
+        std::env::set_var(
+            format!("{prefix}_EMAIL_CLIENT__AUTHORIZATION_TOKEN"),
+            "ignored",
+        );
+        std::env::set_var(
+            format!("{prefix}_EMAIL_CLIENT__AUTHORIZATION_TOKEN_FILE"),
+            &path,
+        );
+
+        let secret = EnvSecretProvider::new(&prefix)
+            .secret("email_client.authorization_token")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(secret.expose_secret(), "from-file");
+    }
+
+    #[test]
+    fn env_provider_fails_if_the_indirected_file_is_missing() {
+        let prefix = env_prefix();
+        std::env::set_var(
+            format!("{prefix}_DATABASE__PASSWORD_FILE"),
+            "/does/not/exist",
+        );
+
+        assert_err!(EnvSecretProvider::new(&prefix).secret("database.password"));
+    }
+
+    #[test]
+    fn secret_source_overrides_earlier_layers() {
+        let directory = secrets_directory();
+        std::fs::write(directory.join("database.password"), "from-file").unwrap();
+
+        let settings = config::Config::builder()
+            .set_default("database.password", "from-yaml")
+            .unwrap()
+            .add_source(SecretSource::new(FileSecretProvider::new(&directory)))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            settings.get_string("database.password").unwrap(),
+            "from-file"
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use hickory_resolver::{error::ResolveErrorKind, TokioAsyncResolver};
+
+use crate::{
+    configuration::DeliverabilitySettings,
+    domain::{SubscriberEmail, SubscriberEmailError},
+};
+
+/// The cache is cleared of expired entries once it holds this many.
+const MAX_CACHED_DOMAINS: usize = 10_000;
+
+/// Providers most subscribers use, to suggest corrections for typos.
+const COMMON_PROVIDERS: &[&str] = &[
+    "aol.com",
+    "comcast.net",
+    "gmail.com",
+    "gmx.com",
+    "gmx.de",
+    "googlemail.com",
+    "hotmail.com",
+    "icloud.com",
+    "live.com",
+    "mail.ru",
+    "outlook.com",
+    "proton.me",
+    "protonmail.com",
+    "web.de",
+    "yahoo.com",
+    "yandex.ru",
+];
+
+/// Misspellings of `.com`.
+const COM_TYPOS: &[&str] = &["con", "cmo", "ocm", "vom", "xom", "comm"];
+
+/// Looks up whether a domain can receive email.
+#[async_trait]
+pub trait DomainResolver: Send + Sync {
+    /// `Ok(false)` if the domain has no MX records and no A/AAAA records to
+    /// fall back to, or explicitly refuses mail with a null MX record.
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, anyhow::Error>;
+}
+
+/// Resolves domains with the system's DNS configuration.
+pub struct DnsResolver(TokioAsyncResolver);
+
+impl DnsResolver {
+    pub fn from_system_conf() -> Result<Self, anyhow::Error> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .context("Failed to read the system's DNS configuration.")?;
+        Ok(Self(resolver))
+    }
+}
+
+#[async_trait]
+impl DomainResolver for DnsResolver {
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        // The trailing dot keeps search domains out of the lookup.
+        let name = format!("{domain}.");
+        match self.0.mx_lookup(name.as_str()).await {
+            // A single MX record pointing at the root is a "null MX" (RFC 7505).
+            Ok(mx) => return Ok(mx.iter().any(|record| !record.exchange().is_root())),
+            Err(e) if !matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                return Err(e.into())
+            }
+            Err(_) => {}
+        }
+        match self.0.lookup_ip(name.as_str()).await {
+            Ok(ips) => Ok(ips.iter().next().is_some()),
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Resolves domains from a fixed list, for tests. Unknown domains do not
+/// accept mail.
+#[derive(Default)]
+pub struct InMemoryResolver(pub HashMap<String, bool>);
+
+#[async_trait]
+impl DomainResolver for InMemoryResolver {
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        Ok(self.0.get(domain).copied().unwrap_or(false))
+    }
+}
+
+/// Rejects addresses at domains that cannot receive email, which would only
+/// bounce and hurt our sender reputation.
+pub struct DeliverabilityCheck {
+    resolver: Arc<dyn DomainResolver>,
+    timeout: Duration,
+    cache_ttl: Duration,
+    cache: Mutex<HashMap<String, (bool, Instant)>>,
+}
+
+impl DeliverabilityCheck {
+    pub fn new(resolver: Arc<dyn DomainResolver>, timeout: Duration, cache_ttl: Duration) -> Self {
+        Self {
+            resolver,
+            timeout,
+            cache_ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `None` if the check is disabled.
+    pub fn from_settings(settings: &DeliverabilitySettings) -> Result<Option<Self>, anyhow::Error> {
+        if !settings.enabled {
+            return Ok(None);
+        }
+        Ok(Some(Self::new(
+            Arc::new(DnsResolver::from_system_conf()?),
+            Duration::from_millis(settings.timeout_milliseconds),
+            Duration::from_secs(settings.cache_ttl_seconds),
+        )))
+    }
+
+    /// If the domain turns out not to accept mail, the error suggests a
+    /// correction when it looks like a typo of a common provider.
+    ///
+    /// Lookups that fail or time out let the address through: a slow DNS
+    /// server should not stop people from subscribing.
+    #[tracing::instrument(name = "Checking that an email domain accepts mail", skip(self, email))]
+    pub async fn check(&self, email: &SubscriberEmail) -> Result<(), SubscriberEmailError> {
+        let domain = email.domain();
+        let accepts_mail = match self.cached(domain) {
+            Some(accepts_mail) => accepts_mail,
+            None => {
+                match tokio::time::timeout(self.timeout, self.resolver.accepts_mail(domain)).await {
+                    Ok(Ok(accepts_mail)) => {
+                        self.store(domain, accepts_mail);
+                        accepts_mail
+                    }
+                    Ok(Err(e)) => {
+                        tracing::warn!(
+                            error.cause_chain = ?e,
+                            error.message = %e,
+                            domain,
+                            "Failed to resolve an email domain, letting the address through."
+                        );
+                        true
+                    }
+                    Err(_) => {
+                        tracing::warn!(
+                            domain,
+                            "Timed out resolving an email domain, letting the address through."
+                        );
+                        true
+                    }
+                }
+            }
+        };
+
+        if accepts_mail {
+            Ok(())
+        } else {
+            Err(SubscriberEmailError::UndeliverableDomain {
+                domain: domain.to_string(),
+                suggestion: suggest_domain(domain)
+                    .map(|domain| format!("{}@{domain}", email.local_part())),
+            })
+        }
+    }
+
+    fn cached(&self, domain: &str) -> Option<bool> {
+        let cache = self.cache.lock().unwrap();
+        cache
+            .get(domain)
+            .filter(|(_, resolved_at)| resolved_at.elapsed() < self.cache_ttl)
+            .map(|(accepts_mail, _)| *accepts_mail)
+    }
+
+    fn store(&self, domain: &str, accepts_mail: bool) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHED_DOMAINS {
+            cache.retain(|_, (_, resolved_at)| resolved_at.elapsed() < self.cache_ttl);
+        }
+        cache.insert(domain.to_string(), (accepts_mail, Instant::now()));
+    }
+}
+
+/// The domain the subscriber probably meant, if `domain` looks like a typo of
+/// a common provider or of a `.com` domain.
+pub fn suggest_domain(domain: &str) -> Option<String> {
+    let fixed_tld = match domain.rsplit_once('.') {
+        Some((name, tld)) if COM_TYPOS.contains(&tld) => format!("{name}.com"),
+        _ => domain.to_string(),
+    };
+    let closest_provider = COMMON_PROVIDERS
+        .iter()
+        .map(|provider| (provider, strsim::damerau_levenshtein(&fixed_tld, provider)))
+        .filter(|(provider, distance)| {
+            // One edit is already a lot for short names like `web.de`.
+            let max_distance = if provider.len() > 8 { 2 } else { 1 };
+            *distance <= max_distance
+        })
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(provider, _)| provider.to_string());
+
+    closest_provider
+        .or(Some(fixed_tld))
+        .filter(|suggestion| suggestion != domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use async_trait::async_trait;
+    use claims::{assert_err_eq, assert_ok};
+
+    use super::{suggest_domain, DeliverabilityCheck, DomainResolver, InMemoryResolver};
+    use crate::domain::{SubscriberEmail, SubscriberEmailError};
+
+    fn email(email: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(email.to_string()).unwrap()
+    }
+
+    fn check(resolver: impl DomainResolver + 'static) -> DeliverabilityCheck {
+        DeliverabilityCheck::new(
+            Arc::new(resolver),
+            Duration::from_millis(100),
+            Duration::from_secs(60),
+        )
+    }
+
+    fn resolver(domains: &[(&str, bool)]) -> InMemoryResolver {
+        InMemoryResolver(
+            domains
+                .iter()
+                .map(|(domain, accepts_mail)| (domain.to_string(), *accepts_mail))
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn domains_that_accept_mail_pass() {
+        let check = check(resolver(&[("gmail.com", true)]));
+
+        assert_ok!(check.check(&email("ursula@gmail.com")).await);
+    }
+
+    #[tokio::test]
+    async fn undeliverable_domains_are_rejected_with_a_suggestion() {
+        let check = check(resolver(&[("gmail.com", true)]));
+
+        assert_err_eq!(
+            check.check(&email("ursula@gmial.con")).await,
+            SubscriberEmailError::UndeliverableDomain {
+                domain: "gmial.con".into(),
+                suggestion: Some("ursula@gmail.com".into()),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn the_error_tells_the_subscriber_what_they_probably_meant() {
+        let check = check(resolver(&[]));
+
+        let error = check.check(&email("ursula@hotmial.com")).await.unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Addresses at hotmial.com cannot receive email. Did you mean ursula@hotmail.com?"
+        );
+    }
+
+    struct SlowResolver;
+
+    #[async_trait]
+    impl DomainResolver for SlowResolver {
+        async fn accepts_mail(&self, _domain: &str) -> Result<bool, anyhow::Error> {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(false)
+        }
+    }
+
+    #[tokio::test]
+    async fn lookups_that_time_out_let_the_address_through() {
+        let check = check(SlowResolver);
+
+        assert_ok!(check.check(&email("ursula@example.com")).await);
+    }
+
+    #[tokio::test]
+    async fn results_are_cached() {
+        let check = check(resolver(&[]));
+        check.store("example.com", true);
+
+        assert_ok!(check.check(&email("ursula@example.com")).await);
+    }
+
+    #[test]
+    fn typos_of_common_providers_are_corrected() {
+        let cases = HashMap::from([
+            ("gmial.com", Some("gmail.com")),
+            ("gmail.con", Some("gmail.com")),
+            ("hotmial.com", Some("hotmail.com")),
+            ("yaho.com", Some("yahoo.com")),
+            ("example.cmo", Some("example.com")),
+            ("gmail.com", None),
+            ("example.org", None),
+            ("example.de", None),
+        ]);
+
+        for (domain, expected) in cases {
+            assert_eq!(suggest_domain(domain).as_deref(), expected, "{domain}");
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, QueryBuilder, Row, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    configuration::DigestSettings,
+    domain::{EmailFormat, SubscriberEmail},
+    email_outbox::enqueue_email,
+    routes::pages::escape_html,
+    segment::Segment,
+    subscription_tokens::{SubscriberLinks, TokenHasher},
+};
+
+/// Outcome of a digest run.
+#[derive(Debug, Default)]
+pub struct DigestReport {
+    /// Digests put in the outbox.
+    pub sent: usize,
+    /// Subscribers whose digest was due but had nothing new.
+    pub skipped: usize,
+}
+
+/// An issue as it appears in a digest.
+pub struct DigestIssue {
+    pub title: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub published_at: DateTime<Utc>,
+}
+
+/// A digest ready to be sent.
+#[derive(Debug)]
+pub struct DigestEmail {
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+}
+
+/// A subscriber whose digest is due.
+struct DueSubscriber {
+    id: Uuid,
+    email: String,
+    email_format: String,
+    since: DateTime<Utc>,
+}
+
+struct CandidateIssue {
+    title: String,
+    html_content: String,
+    text_content: String,
+    published_at: DateTime<Utc>,
+    segment: Option<String>,
+}
+
+/// Put together every digest that is due, for subscribers on
+/// `weekly_digest`. The issues they would have received since their last
+/// digest, `last_digest_at`, are combined into one email and put in the
+/// [outbox](crate::email_outbox). Nothing is sent if nothing new was
+/// published.
+///
+/// Subscribers are claimed with `FOR UPDATE SKIP LOCKED`, so several instances
+/// can run this at once without sending a digest twice.
+#[tracing::instrument(name = "Putting together weekly digests", skip_all)]
+pub async fn enqueue_due_digests(
+    db_pool: &PgPool,
+    base_url: &str,
+    token_hasher: &TokenHasher,
+    settings: &DigestSettings,
+) -> Result<DigestReport, anyhow::Error> {
+    let interval = chrono::Duration::from_std(settings.interval())
+        .context("The digest interval is out of range.")?;
+    let mut report = DigestReport::default();
+    loop {
+        let until = Utc::now();
+        let mut transaction = db_pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool.")?;
+        let Some(subscriber) = dequeue_due_subscriber(&mut transaction, until - interval)
+            .await
+            .context("Failed to look for a due digest.")?
+        else {
+            break;
+        };
+
+        let issues = get_digest_issues(&mut transaction, &subscriber, until)
+            .await
+            .context("Failed to retrieve the issues of a digest.")?;
+        match SubscriberEmail::parse(subscriber.email.clone()) {
+            Ok(_) if issues.is_empty() => report.skipped += 1,
+            Ok(recipient) => {
+                let links = SubscriberLinks::new(base_url, token_hasher, subscriber.id);
+                let digest = render_digest(&issues, &links);
+                let html_content = match subscriber.email_format.parse() {
+                    Ok(EmailFormat::PlainText) => "",
+                    _ => digest.html_content.as_str(),
+                };
+                enqueue_email(
+                    &mut transaction,
+                    &recipient,
+                    &digest.subject,
+                    html_content,
+                    &digest.text_content,
+                )
+                .await
+                .context("Failed to add a digest to the outbox.")?;
+                report.sent += 1;
+            }
+            Err(error) => {
+                tracing::warn!(
+                    error.cause_chain = ?error,
+                    "Skipping a digest. The stored contact details are invalid."
+                );
+                report.skipped += 1;
+            }
+        }
+
+        sqlx::query!(
+            "UPDATE subscriptions SET last_digest_at = $2 WHERE id = $1",
+            subscriber.id,
+            until
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to move the digest cursor.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to record a digest.")?;
+    }
+
+    Ok(report)
+}
+
+/// Put together the digests that are due every `poll_interval`, until the
+/// process stops.
+pub async fn enqueue_digests_until_stopped(
+    db_pool: PgPool,
+    base_url: String,
+    token_hasher: TokenHasher,
+    settings: DigestSettings,
+) {
+    let mut interval = tokio::time::interval(settings.poll_interval());
+    loop {
+        interval.tick().await;
+        match enqueue_due_digests(&db_pool, &base_url, &token_hasher, &settings).await {
+            Ok(report) if report.sent + report.skipped > 0 => {
+                tracing::info!(?report, "Put together weekly digests.");
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to put together weekly digests."
+            ),
+        }
+    }
+}
+
+/// Combine `issues`, oldest first, into one email.
+pub fn render_digest(issues: &[DigestIssue], links: &SubscriberLinks) -> DigestEmail {
+    let subject = match issues {
+        [issue] => format!("Your weekly digest: {}", issue.title),
+        issues => format!("Your weekly digest: {} new issues", issues.len()),
+    };
+
+    let mut html_content = String::from("<h1>Your weekly digest</h1>\n<ul>\n");
+    for issue in issues {
+        html_content.push_str(&format!("<li>{}</li>\n", escape_html(&issue.title)));
+    }
+    html_content.push_str("</ul>\n");
+    for issue in issues {
+        html_content.push_str(&format!(
+            "<hr>\n<h2>{}</h2>\n<p><small>Published on {}</small></p>\n{}\n",
+            escape_html(&issue.title),
+            issue.published_at.format("%B %-d, %Y"),
+            issue.html_content
+        ));
+    }
+    html_content.push_str("<hr>\n");
+    html_content.push_str(&links.html_footer());
+
+    let mut text_content = String::from("Your weekly digest\n");
+    for issue in issues {
+        text_content.push_str(&format!(
+            "\n== {} ==\nPublished on {}\n\n{}\n",
+            issue.title,
+            issue.published_at.format("%B %-d, %Y"),
+            issue.text_content
+        ));
+    }
+    text_content.push('\n');
+    text_content.push_str(&links.text_footer());
+
+    DigestEmail {
+        subject,
+        html_content,
+        text_content,
+    }
+}
+
+async fn dequeue_due_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    due_before: DateTime<Utc>,
+) -> Result<Option<DueSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        DueSubscriber,
+        r#"
+        SELECT id, email, email_format, COALESCE(last_digest_at, subscribed_at) AS "since!"
+        FROM subscriptions
+        WHERE status = 'confirmed'
+            AND frequency = 'weekly_digest'
+            AND COALESCE(last_digest_at, subscribed_at) <= $1
+        ORDER BY 4
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+        due_before
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+}
+
+/// The issues published since the last digest of `subscriber` that they
+/// would have received: about a topic they picked, and in the segment of the
+/// issue as they are now.
+async fn get_digest_issues(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber: &DueSubscriber,
+    until: DateTime<Utc>,
+) -> Result<Vec<DigestIssue>, anyhow::Error> {
+    let candidates = sqlx::query_as!(
+        CandidateIssue,
+        r#"
+        SELECT title, html_content, text_content, published_at, segment
+        FROM newsletter_issues i
+        WHERE published_at > $2
+            AND published_at <= $3
+            AND (
+                i.topic IS NULL
+                OR NOT EXISTS (SELECT 1 FROM subscriber_topics t WHERE t.subscriber_id = $1)
+                OR EXISTS (
+                    SELECT 1 FROM subscriber_topics t
+                    WHERE t.subscriber_id = $1 AND t.topic = i.topic
+                )
+            )
+        ORDER BY published_at
+        "#,
+        subscriber.id,
+        subscriber.since,
+        until
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    let mut issues = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        if let Some(segment) = &candidate.segment {
+            let segment = match Segment::parse(segment) {
+                Ok(segment) => segment,
+                Err(error) => {
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        title = candidate.title,
+                        "Leaving an issue with an invalid segment out of a digest."
+                    );
+                    continue;
+                }
+            };
+            let mut query = QueryBuilder::<Postgres>::new(
+                "SELECT EXISTS (SELECT 1 FROM subscriptions s WHERE s.id = ",
+            );
+            query.push_bind(subscriber.id).push(" AND ");
+            segment.push_sql(&mut query);
+            query.push(")");
+            let in_segment: bool = query
+                .build()
+                .fetch_one(&mut **transaction)
+                .await?
+                .try_get(0)?;
+            if !in_segment {
+                continue;
+            }
+        }
+        issues.push(DigestIssue {
+            title: candidate.title,
+            html_content: candidate.html_content,
+            text_content: candidate.text_content,
+            published_at: candidate.published_at,
+        });
+    }
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{render_digest, DigestIssue};
+    use crate::subscription_tokens::SubscriberLinks;
+
+    fn links() -> SubscriberLinks {
+        SubscriberLinks {
+            preferences: "https://example.com/preferences?token=t".into(),
+            unsubscribe: "https://example.com/unsubscribe?token=t".into(),
+        }
+    }
+
+    fn issue(title: &str) -> DigestIssue {
+        DigestIssue {
+            title: title.into(),
+            html_content: format!("<p>{title} as HTML</p>"),
+            text_content: format!("{title} as text"),
+            published_at: Utc.with_ymd_and_hms(2025, 4, 7, 9, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn a_digest_lists_and_includes_every_issue() {
+        let digest = render_digest(&[issue("First <issue>"), issue("Second")], &links());
+
+        assert_eq!(digest.subject, "Your weekly digest: 2 new issues");
+        assert!(digest.html_content.contains("<li>First &lt;issue&gt;</li>"));
+        assert!(digest.html_content.contains("<p>Second as HTML</p>"));
+        assert!(digest.html_content.contains("Published on April 7, 2025"));
+        assert!(digest.text_content.contains("== Second ==\n"));
+        assert!(digest
+            .html_content
+            .contains("https://example.com/unsubscribe?token=t"));
+        assert!(digest.text_content.ends_with(
+            "Manage your preferences: https://example.com/preferences?token=t\nUnsubscribe: https://example.com/unsubscribe?token=t"
+        ));
+    }
+
+    #[test]
+    fn a_digest_of_one_issue_is_named_after_it() {
+        let digest = render_digest(&[issue("Only")], &links());
+
+        assert_eq!(digest.subject, "Your weekly digest: Only");
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+
+use super::{SubscriberEmail, SubscriberEmailError};
+
+/// Disposable email providers shipped with the application.
+const BUNDLED_DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// Local parts that belong to a function rather than a person. Mail to them
+/// tends to bounce, go unread or be reported as spam.
+const ROLE_LOCAL_PARTS: &[&str] = &[
+    "abuse",
+    "admin",
+    "administrator",
+    "do-not-reply",
+    "donotreply",
+    "hostmaster",
+    "mailer-daemon",
+    "no-reply",
+    "noreply",
+    "postmaster",
+    "root",
+    "webmaster",
+];
+
+/// Which addresses may subscribe, on top of being syntactically valid.
+///
+/// Domains match their subdomains as well. An allowed domain is exempt from
+/// the disposable and denied lists, but not from the role address rule.
+#[derive(Debug, Clone, Default)]
+pub struct EmailPolicy {
+    pub disposable_domains: HashSet<String>,
+    pub reject_role_addresses: bool,
+    pub allowed_domains: HashSet<String>,
+    pub denied_domains: HashSet<String>,
+}
+
+impl EmailPolicy {
+    /// The domains in the bundled list.
+    pub fn bundled_disposable_domains() -> impl Iterator<Item = String> {
+        parse_domain_list(BUNDLED_DISPOSABLE_DOMAINS)
+    }
+
+    pub fn check(&self, email: &SubscriberEmail) -> Result<(), SubscriberEmailError> {
+        let domain = normalize_domain(email.domain());
+        if self.reject_role_addresses && is_role_address(email.local_part()) {
+            return Err(SubscriberEmailError::RoleAddress(
+                email.as_ref().to_string(),
+            ));
+        }
+        if matches(&self.allowed_domains, &domain) {
+            return Ok(());
+        }
+        if matches(&self.denied_domains, &domain) {
+            return Err(SubscriberEmailError::DeniedDomain(domain));
+        }
+        if matches(&self.disposable_domains, &domain) {
+            return Err(SubscriberEmailError::DisposableDomain(domain));
+        }
+        Ok(())
+    }
+}
+
+/// One domain per line, ignoring blank lines and `#` comments.
+pub(crate) fn parse_domain_list(list: &str) -> impl Iterator<Item = String> + '_ {
+    list.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(normalize_domain)
+}
+
+pub(crate) fn normalize_domain(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_lowercase()
+}
+
+/// Whether `domain`, or one of its parent domains, is in `domains`.
+fn matches(domains: &HashSet<String>, domain: &str) -> bool {
+    let mut candidate = domain;
+    loop {
+        if domains.contains(candidate) {
+            return true;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) => candidate = parent,
+            None => return false,
+        }
+    }
+}
+
+fn is_role_address(local_part: &str) -> bool {
+    // `noreply+newsletter@` is still `noreply@`.
+    let local_part = local_part.split('+').next().unwrap_or_default();
+    ROLE_LOCAL_PARTS.contains(&local_part.to_lowercase().as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err_eq, assert_ok};
+
+    use super::EmailPolicy;
+    use crate::domain::{SubscriberEmail, SubscriberEmailError};
+
+    fn email(email: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(email.to_string()).unwrap()
+    }
+
+    fn policy() -> EmailPolicy {
+        EmailPolicy {
+            disposable_domains: EmailPolicy::bundled_disposable_domains().collect(),
+            ..Default::default()
+        }
+    }
+
+    fn domains(domains: &[&str]) -> std::collections::HashSet<String> {
+        domains.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn the_bundled_list_skips_comments() {
+        let domains: Vec<_> = EmailPolicy::bundled_disposable_domains().collect();
+
+        assert!(domains.contains(&"mailinator.com".to_string()));
+        assert!(domains.iter().all(|d| !d.starts_with('#')));
+    }
+
+    #[test]
+    fn disposable_domains_and_their_subdomains_are_rejected() {
+        let policy = policy();
+
+        assert_err_eq!(
+            policy.check(&email("ursula@Mailinator.com")),
+            SubscriberEmailError::DisposableDomain("mailinator.com".into())
+        );
+        assert_err_eq!(
+            policy.check(&email("ursula@eu.mailinator.com")),
+            SubscriberEmailError::DisposableDomain("eu.mailinator.com".into())
+        );
+        assert_ok!(policy.check(&email("ursula@notmailinator.com")));
+    }
+
+    #[test]
+    fn role_addresses_are_only_rejected_when_enabled() {
+        let mut policy = policy();
+        assert_ok!(policy.check(&email("noreply@example.com")));
+
+        policy.reject_role_addresses = true;
+
+        assert_err_eq!(
+            policy.check(&email("NoReply+news@example.com")),
+            SubscriberEmailError::RoleAddress("NoReply+news@example.com".into())
+        );
+        assert_err_eq!(
+            policy.check(&email("postmaster@example.com")),
+            SubscriberEmailError::RoleAddress("postmaster@example.com".into())
+        );
+        assert_ok!(policy.check(&email("ursula@example.com")));
+    }
+
+    #[test]
+    fn denied_domains_are_rejected() {
+        let policy = EmailPolicy {
+            denied_domains: domains(&["example.org"]),
+            ..policy()
+        };
+
+        assert_err_eq!(
+            policy.check(&email("ursula@mail.example.org")),
+            SubscriberEmailError::DeniedDomain("mail.example.org".into())
+        );
+    }
+
+    #[test]
+    fn allowed_domains_override_the_other_lists() {
+        let policy = EmailPolicy {
+            allowed_domains: domains(&["mailinator.com"]),
+            denied_domains: domains(&["mailinator.com"]),
+            ..policy()
+        };
+
+        assert_ok!(policy.check(&email("ursula@mailinator.com")));
+    }
+}
@@ -1,7 +1,12 @@
+mod email_policy;
 mod new_subscriber;
+mod preferences;
 mod subscriber_email;
 mod subscriber_name;
 
-pub use new_subscriber::NewSubscriber;
-pub use subscriber_email::SubscriberEmail;
-pub use subscriber_name::SubscriberName;
+pub use email_policy::EmailPolicy;
+pub(crate) use email_policy::{normalize_domain, parse_domain_list};
+pub use new_subscriber::{FieldError, NewSubscriber, ValidationErrors};
+pub use preferences::{EmailFormat, Frequency, UnknownTopicError};
+pub use subscriber_email::{SubscriberEmail, SubscriberEmailError};
+pub use subscriber_name::{SubscriberName, SubscriberNameError};
@@ -1,6 +1,70 @@
-use super::{subscriber_name::SubscriberName, SubscriberEmail};
+use std::fmt::Display;
+
+use super::{
+    subscriber_name::SubscriberName, SubscriberEmail, SubscriberEmailError, SubscriberNameError,
+    UnknownTopicError,
+};
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    /// Where they signed up, e.g. `blog` or `conference-2025`.
+    pub source: Option<String>,
+}
+
+/// A rule broken by one of the fields of a new subscriber or of their
+/// preferences.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum FieldError {
+    #[error(transparent)]
+    Name(#[from] SubscriberNameError),
+    #[error(transparent)]
+    Email(#[from] SubscriberEmailError),
+    #[error(transparent)]
+    Topic(#[from] UnknownTopicError),
+}
+
+impl FieldError {
+    /// The name of the offending field.
+    pub fn field(&self) -> &'static str {
+        match self {
+            FieldError::Name(_) => "name",
+            FieldError::Email(_) => "email",
+            FieldError::Topic(_) => "topics",
+        }
+    }
+}
+
+/// Every rule broken by a new subscriber, so that they can all be fixed in
+/// one go.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ValidationErrors(pub Vec<FieldError>);
+
+impl ValidationErrors {
+    pub fn push(&mut self, error: impl Into<FieldError>) {
+        self.0.push(error.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &FieldError> {
+        self.0.iter()
+    }
+}
+
+impl Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let messages: Vec<_> = self.0.iter().map(ToString::to_string).collect();
+        write!(f, "{}", messages.join(" "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl From<SubscriberEmailError> for ValidationErrors {
+    fn from(error: SubscriberEmailError) -> Self {
+        Self(vec![error.into()])
+    }
 }
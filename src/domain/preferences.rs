@@ -0,0 +1,100 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Which body of an issue a subscriber receives.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailFormat {
+    /// The HTML body, with the plain-text one as a fallback.
+    #[default]
+    Html,
+    PlainText,
+}
+
+/// How often a subscriber hears from us.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Frequency {
+    /// Every issue, as soon as it is published.
+    #[default]
+    EveryIssue,
+    /// A weekly digest of the issues published that week.
+    WeeklyDigest,
+}
+
+impl EmailFormat {
+    /// The value stored in `subscriptions.email_format`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailFormat::Html => "html",
+            EmailFormat::PlainText => "plain_text",
+        }
+    }
+}
+
+impl FromStr for EmailFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(EmailFormat::Html),
+            "plain_text" => Ok(EmailFormat::PlainText),
+            other => Err(format!("`{other}` is not an email format.")),
+        }
+    }
+}
+
+impl Frequency {
+    /// The value stored in `subscriptions.frequency`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Frequency::EveryIssue => "every_issue",
+            Frequency::WeeklyDigest => "weekly_digest",
+        }
+    }
+}
+
+impl FromStr for Frequency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "every_issue" => Ok(Frequency::EveryIssue),
+            "weekly_digest" => Ok(Frequency::WeeklyDigest),
+            other => Err(format!("`{other}` is not a frequency.")),
+        }
+    }
+}
+
+/// A topic that is not in `topics`.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("There is no topic called `{0}`.")]
+pub struct UnknownTopicError(pub String);
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailFormat, Frequency};
+
+    #[test]
+    fn stored_values_round_trip() {
+        for format in [EmailFormat::Html, EmailFormat::PlainText] {
+            assert_eq!(format.as_str().parse::<EmailFormat>(), Ok(format));
+        }
+        for frequency in [Frequency::EveryIssue, Frequency::WeeklyDigest] {
+            assert_eq!(frequency.as_str().parse::<Frequency>(), Ok(frequency));
+        }
+    }
+
+    #[test]
+    fn serde_names_match_the_stored_values() {
+        assert_eq!(
+            serde_json::to_value(EmailFormat::PlainText).unwrap(),
+            EmailFormat::PlainText.as_str()
+        );
+        assert_eq!(
+            serde_json::to_value(Frequency::WeeklyDigest).unwrap(),
+            Frequency::WeeklyDigest.as_str()
+        );
+    }
+}
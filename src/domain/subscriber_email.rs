@@ -2,28 +2,95 @@ use std::fmt::Display;
 
 use validator::ValidateEmail;
 
+/// An email address in canonical form: surrounding whitespace trimmed and
+/// the domain lower-cased, with internationalized domains punycode-encoded.
+/// The local part is kept as is, as mail servers may tell its case apart.
+///
+/// The address as the subscriber typed it is kept for display.
 #[derive(Debug)]
-pub struct SubscriberEmail(String);
+pub struct SubscriberEmail {
+    canonical: String,
+    original: String,
+}
+
+/// Why an email address was turned down.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SubscriberEmailError {
+    #[error("{0} is not a valid subscriber email.")]
+    InvalidSyntax(String),
+    #[error("Addresses at {0} are not accepted: it is a disposable email provider.")]
+    DisposableDomain(String),
+    #[error("Addresses at {0} are not accepted.")]
+    DeniedDomain(String),
+    #[error("{0} is a role address, please subscribe with a personal address.")]
+    RoleAddress(String),
+    #[error(
+        "Addresses at {domain} cannot receive email.{}",
+        did_you_mean(suggestion)
+    )]
+    UndeliverableDomain {
+        domain: String,
+        suggestion: Option<String>,
+    },
+}
+
+fn did_you_mean(suggestion: &Option<String>) -> String {
+    suggestion
+        .as_ref()
+        .map(|s| format!(" Did you mean {s}?"))
+        .unwrap_or_default()
+}
 
 impl SubscriberEmail {
-    pub fn parse(email: String) -> Result<Self, String> {
-        if email.validate_email() {
-            Ok(Self(email))
-        } else {
-            Err(format!("{email} is not a vlid subscriber email."))
+    /// Checks the syntax only. Subscriber addresses must also pass an
+    /// [`EmailPolicy`](super::EmailPolicy).
+    pub fn parse(email: String) -> Result<Self, SubscriberEmailError> {
+        let original = email.trim();
+        if !original.validate_email() {
+            return Err(SubscriberEmailError::InvalidSyntax(email));
         }
+        let Some((local_part, domain)) = original.rsplit_once('@') else {
+            return Err(SubscriberEmailError::InvalidSyntax(email));
+        };
+        let Ok(domain) = idna::domain_to_ascii(domain) else {
+            return Err(SubscriberEmailError::InvalidSyntax(email));
+        };
+        Ok(Self {
+            canonical: format!("{local_part}@{domain}"),
+            original: original.to_string(),
+        })
+    }
+
+    /// The address as the subscriber typed it, for display.
+    pub fn original(&self) -> &str {
+        &self.original
+    }
+
+    /// The part before the `@`.
+    pub fn local_part(&self) -> &str {
+        self.canonical
+            .rsplit_once('@')
+            .map_or("", |(local, _)| local)
+    }
+
+    /// The part after the `@`, in canonical form.
+    pub fn domain(&self) -> &str {
+        self.canonical
+            .rsplit_once('@')
+            .map_or("", |(_, domain)| domain)
     }
 }
 
+/// The canonical form.
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.canonical
     }
 }
 
 impl Display for SubscriberEmail {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        self.canonical.fmt(f)
     }
 }
 
@@ -53,6 +120,23 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn the_domain_is_lower_cased_and_whitespace_trimmed() {
+        let email = SubscriberEmail::parse("  Ursula@Example.COM \n".to_string()).unwrap();
+
+        assert_eq!(email.as_ref(), "Ursula@example.com");
+        assert_eq!(email.original(), "Ursula@Example.COM");
+    }
+
+    #[test]
+    fn internationalized_domains_are_punycode_encoded() {
+        let email = SubscriberEmail::parse("ursula@Bücher.example".to_string()).unwrap();
+
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.example");
+        assert_eq!(email.domain(), "xn--bcher-kva.example");
+        assert_eq!(email.original(), "ursula@Bücher.example");
+    }
+
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
 
@@ -1,18 +1,54 @@
 use unicode_segmentation::UnicodeSegmentation;
 
+const MAX_LENGTH: usize = 256;
+const FORBIDDEN_CHARACTERS: [char; 9] = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
+
 #[derive(Debug)]
 pub struct SubscriberName(String);
 
+/// Why a name was turned down.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SubscriberNameError {
+    #[error("The name must not be empty.")]
+    Empty,
+    #[error("The name must be at most {MAX_LENGTH} characters long.")]
+    TooLong,
+    #[error("The name must not contain {}.", list_characters(.0))]
+    ForbiddenCharacters(Vec<char>),
+}
+
+fn list_characters(characters: &[char]) -> String {
+    characters
+        .iter()
+        .map(|c| format!("`{c}`"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 impl SubscriberName {
-    pub fn parse(name: String) -> Result<Self, String> {
-        let is_empty_or_whitespace = name.trim().is_empty();
-        let is_too_long = name.graphemes(true).count() > 256;
-        let forbidden_characters = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
-        let contains_forbidden_characters = name.chars().any(|g| forbidden_characters.contains(&g));
-        if is_empty_or_whitespace || is_too_long || contains_forbidden_characters {
-            Err(format!("{name} is not a valid subscriber name."))
-        } else {
+    /// Fails with every rule the name breaks.
+    pub fn parse(name: String) -> Result<Self, Vec<SubscriberNameError>> {
+        let mut errors = Vec::new();
+        if name.trim().is_empty() {
+            errors.push(SubscriberNameError::Empty);
+        }
+        if name.graphemes(true).count() > MAX_LENGTH {
+            errors.push(SubscriberNameError::TooLong);
+        }
+        let mut forbidden: Vec<_> = name
+            .chars()
+            .filter(|c| FORBIDDEN_CHARACTERS.contains(c))
+            .collect();
+        forbidden.sort_unstable();
+        forbidden.dedup();
+        if !forbidden.is_empty() {
+            errors.push(SubscriberNameError::ForbiddenCharacters(forbidden));
+        }
+
+        if errors.is_empty() {
             Ok(Self(name))
+        } else {
+            Err(errors)
         }
     }
 }
@@ -25,8 +61,8 @@ impl AsRef<str> for SubscriberName {
 
 #[cfg(test)]
 mod tests {
-    use crate::domain::subscriber_name::SubscriberName;
-    use claims::{assert_err, assert_ok};
+    use crate::domain::subscriber_name::{SubscriberName, SubscriberNameError};
+    use claims::{assert_err, assert_err_eq, assert_ok};
 
     #[test]
     fn a_256_grapheme_long_name_is_valid() {
@@ -60,6 +96,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn every_broken_rule_is_reported() {
+        let name = format!("<{}>", "a".repeat(256));
+        assert_err_eq!(
+            SubscriberName::parse(name),
+            vec![
+                SubscriberNameError::TooLong,
+                SubscriberNameError::ForbiddenCharacters(vec!['<', '>']),
+            ]
+        );
+    }
+
     #[test]
     fn a_valid_name_is_parsed_successfully() {
         let name = "Ursula Le Guin".to_string();
@@ -23,7 +23,10 @@ impl EmailClient {
             Err(_) => return Err("Unable to parse base url.".to_string()),
         };
 
-        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        let http_client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| format!("Unable to build HTTP client: {e}"))?;
 
         Ok(Self {
             base_url,
@@ -71,6 +74,8 @@ struct SendEmailRequest<'a> {
     from: &'a str,
     to: &'a str,
     subject: &'a str,
+    /// Left out for subscribers who only want plain text.
+    #[serde(skip_serializing_if = "str::is_empty")]
     html_body: &'a str,
     text_body: &'a str,
 }
@@ -0,0 +1,255 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{EmailOutboxSettings, SharedRuntimeSettings},
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+};
+
+/// Retries are never further apart than this.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// An email waiting in the outbox.
+pub struct OutboxEmail {
+    pub id: Uuid,
+    pub recipient: String,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub attempts: i32,
+}
+
+/// Outcome of a relay run.
+#[derive(Debug, Default)]
+pub struct RelayReport {
+    pub sent: usize,
+    /// Failed, to be tried again later.
+    pub retried: usize,
+    /// Failed for the last time.
+    pub failed: usize,
+}
+
+/// Store an email to be sent once `transaction` commits, so that it is sent
+/// if and only if the change that triggered it is saved.
+#[tracing::instrument(name = "Adding an email to the outbox", skip_all)]
+pub async fn enqueue_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let id = Uuid::new_v4();
+    let now = Utc::now();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO email_outbox (
+            id, recipient, subject, html_content, text_content,
+            status, next_attempt_at, created_at, updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5, 'pending', $6, $6, $6)
+        "#,
+        id,
+        recipient.as_ref(),
+        subject,
+        html_content,
+        text_content,
+        now
+    );
+
+    transaction.execute(query).await?;
+    Ok(id)
+}
+
+/// Send every email that is due, recording the outcome of each attempt.
+///
+/// Emails are claimed with `FOR UPDATE SKIP LOCKED`, so several instances can
+/// relay the same outbox without sending an email twice.
+#[tracing::instrument(name = "Relaying the email outbox", skip_all)]
+pub async fn relay_pending_emails(
+    db_pool: &PgPool,
+    email_client: &EmailClient,
+    settings: &EmailOutboxSettings,
+) -> Result<RelayReport, anyhow::Error> {
+    let mut report = RelayReport::default();
+    while let Some((mut transaction, email)) = dequeue_email(db_pool)
+        .await
+        .context("Failed to dequeue an email from the outbox.")?
+    {
+        let outcome = match SubscriberEmail::parse(email.recipient.clone()) {
+            Ok(recipient) => email_client
+                .send_email(
+                    &recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                )
+                .await
+                .map_err(anyhow::Error::from),
+            Err(error) => Err(anyhow::anyhow!(error)),
+        };
+
+        let attempts = email.attempts + 1;
+        let failure = match &outcome {
+            Ok(()) => {
+                report.sent += 1;
+                None
+            }
+            Err(error) => {
+                let gave_up = attempts >= settings.max_attempts as i32;
+                if gave_up {
+                    report.failed += 1;
+                } else {
+                    report.retried += 1;
+                }
+                tracing::error!(
+                    error.cause_chain = ?error,
+                    error.message = %error,
+                    outbox_email_id = %email.id,
+                    attempts,
+                    gave_up,
+                    "Failed to send an email from the outbox."
+                );
+                Some((format!("{error:#}"), gave_up))
+            }
+        };
+
+        record_attempt(&mut transaction, &email, settings, failure)
+            .await
+            .context("Failed to record an outbox delivery attempt.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to record an outbox delivery attempt.")?;
+    }
+
+    Ok(report)
+}
+
+/// Relay the outbox every `poll_interval`, with the email settings in use at
+/// the time, until the process stops.
+pub async fn relay_until_stopped(
+    db_pool: PgPool,
+    runtime_settings: Arc<SharedRuntimeSettings>,
+    settings: EmailOutboxSettings,
+) {
+    let mut interval = tokio::time::interval(settings.poll_interval());
+    loop {
+        interval.tick().await;
+        let runtime_settings = runtime_settings.current();
+        match relay_pending_emails(&db_pool, &runtime_settings.email_client, &settings).await {
+            Ok(report) if report.sent + report.retried + report.failed > 0 => {
+                tracing::info!(?report, "Relayed the email outbox.");
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to relay the email outbox."
+            ),
+        }
+    }
+}
+
+/// How long to wait before attempt number `attempts + 1`.
+fn retry_delay(settings: &EmailOutboxSettings, attempts: i32) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    Duration::from_secs(settings.retry_delay_seconds.saturating_mul(1 << exponent))
+        .min(MAX_RETRY_DELAY)
+}
+
+async fn dequeue_email(
+    db_pool: &PgPool,
+) -> Result<Option<(Transaction<'static, Postgres>, OutboxEmail)>, sqlx::Error> {
+    let mut transaction = db_pool.begin().await?;
+    let email = sqlx::query_as!(
+        OutboxEmail,
+        r#"
+        SELECT id, recipient, subject, html_content, text_content, attempts
+        FROM email_outbox
+        WHERE status = 'pending' AND next_attempt_at <= $1
+        ORDER BY next_attempt_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+        Utc::now()
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    Ok(email.map(|email| (transaction, email)))
+}
+
+/// `failure` is the error and whether we gave up on the email.
+async fn record_attempt(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &OutboxEmail,
+    settings: &EmailOutboxSettings,
+    failure: Option<(String, bool)>,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let attempts = email.attempts + 1;
+    let (status, last_error, next_attempt_at) = match failure {
+        None => ("sent", None, now),
+        Some((error, true)) => ("failed", Some(error), now),
+        Some((error, false)) => (
+            "pending",
+            Some(error),
+            now + retry_delay(settings, attempts),
+        ),
+    };
+    let query = sqlx::query!(
+        r#"
+        UPDATE email_outbox
+        SET status = $2, attempts = $3, last_error = $4, next_attempt_at = $5, updated_at = $6
+        WHERE id = $1
+        "#,
+        email.id,
+        status,
+        attempts,
+        last_error,
+        next_attempt_at,
+        now
+    );
+
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::retry_delay;
+    use crate::configuration::EmailOutboxSettings;
+
+    #[test]
+    fn retries_back_off_exponentially_up_to_an_hour() {
+        let settings = EmailOutboxSettings {
+            retry_delay_seconds: 10,
+            ..Default::default()
+        };
+
+        let delays: Vec<_> = [1, 2, 3, 10, 1000]
+            .into_iter()
+            .map(|attempts| retry_delay(&settings, attempts))
+            .collect();
+
+        assert_eq!(
+            delays,
+            [
+                Duration::from_secs(10),
+                Duration::from_secs(20),
+                Duration::from_secs(40),
+                Duration::from_secs(3600),
+                Duration::from_secs(3600),
+            ]
+        );
+    }
+}
@@ -1,13 +1,30 @@
 use std::fmt::{Debug, Display};
 
-use actix_web::{http::StatusCode, ResponseError};
+use std::time::Duration;
+
+use crate::{
+    domain::{
+        FieldError, SubscriberEmailError, SubscriberNameError, UnknownTopicError, ValidationErrors,
+    },
+    problem::{InvalidParam, Problem},
+    segment::SegmentError,
+};
+use actix_web::{
+    http::{
+        header::{HeaderValue, RETRY_AFTER, WWW_AUTHENTICATE},
+        StatusCode,
+    },
+    HttpResponse, ResponseError,
+};
 
 // --- SUBSCRIBE ERROR --- //
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
-    #[error("{0}")]
-    ValidationError(String),
+    #[error(transparent)]
+    ValidationError(#[from] ValidationErrors),
+    #[error(transparent)]
+    RateLimited(#[from] RateLimitError),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -22,9 +39,48 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::RateLimited(e) => e.status_code(),
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscribeError::ValidationError(errors) => Problem::new(
+                self.status_code(),
+                "validation-error",
+                "Invalid subscription",
+                errors.to_string(),
+            )
+            .with_invalid_params(errors.iter().map(invalid_param).collect())
+            .response(),
+            SubscribeError::RateLimited(e) => e.error_response(),
+            SubscribeError::UnexpectedError(_) => Problem::internal_error().response(),
+        }
+    }
+}
+
+fn invalid_param(error: &FieldError) -> InvalidParam {
+    let problem_type = match error {
+        FieldError::Name(e) => match e {
+            SubscriberNameError::Empty => "empty-name",
+            SubscriberNameError::TooLong => "name-too-long",
+            SubscriberNameError::ForbiddenCharacters(_) => "forbidden-characters",
+        },
+        FieldError::Email(e) => match e {
+            SubscriberEmailError::InvalidSyntax(_) => "invalid-email",
+            SubscriberEmailError::DisposableDomain(_) => "disposable-email",
+            SubscriberEmailError::DeniedDomain(_) => "denied-email-domain",
+            SubscriberEmailError::RoleAddress(_) => "role-address",
+            SubscriberEmailError::UndeliverableDomain { .. } => "undeliverable-email",
+        },
+        FieldError::Topic(_) => "unknown-topic",
+    };
+    InvalidParam {
+        name: error.field(),
+        problem_type,
+        reason: error.to_string(),
+    }
 }
 
 // --- SUBSCRIPTION CONFIRMATION ERROR --- //
@@ -41,7 +97,88 @@ impl Debug for SubscriptionConfirmationError {
     }
 }
 
-impl ResponseError for SubscriptionConfirmationError {}
+impl ResponseError for SubscriptionConfirmationError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        Problem::internal_error().response()
+    }
+}
+
+// --- ARCHIVE ERROR --- //
+
+#[derive(thiserror::Error)]
+pub enum ArchiveError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ArchiveError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        Problem::internal_error().response()
+    }
+}
+
+// --- PREFERENCES ERROR --- //
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("The preferences link is not valid.")]
+    InvalidToken,
+    #[error(transparent)]
+    ValidationError(#[from] ValidationErrors),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PreferencesError::InvalidToken => StatusCode::NOT_FOUND,
+            PreferencesError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PreferencesError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            PreferencesError::InvalidToken => Problem::new(
+                self.status_code(),
+                "invalid-token",
+                "Invalid preferences link",
+                self.to_string(),
+            )
+            .response(),
+            PreferencesError::ValidationError(errors) => Problem::new(
+                self.status_code(),
+                "validation-error",
+                "Invalid preferences",
+                errors.to_string(),
+            )
+            .with_invalid_params(errors.iter().map(invalid_param).collect())
+            .response(),
+            PreferencesError::UnexpectedError(_) => Problem::internal_error().response(),
+        }
+    }
+}
 
 // --- RETRIEVE SUBSCRIBER ERROR --- //
 
@@ -97,6 +234,10 @@ impl std::error::Error for StoreTokenError {
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error(transparent)]
+    UnknownTopic(#[from] UnknownTopicError),
+    #[error(transparent)]
+    InvalidSegment(#[from] SegmentError),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -110,9 +251,204 @@ impl Debug for PublishError {
 impl ResponseError for PublishError {
     fn status_code(&self) -> StatusCode {
         match self {
+            PublishError::UnknownTopic(_) | PublishError::InvalidSegment(_) => {
+                StatusCode::BAD_REQUEST
+            }
             PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            PublishError::UnknownTopic(_) => Problem::new(
+                self.status_code(),
+                "unknown-topic",
+                "Unknown topic",
+                self.to_string(),
+            )
+            .response(),
+            PublishError::InvalidSegment(_) => Problem::new(
+                self.status_code(),
+                "invalid-segment",
+                "Invalid segment",
+                self.to_string(),
+            )
+            .response(),
+            PublishError::UnexpectedError(_) => Problem::internal_error().response(),
+        }
+    }
+}
+
+// --- DRAFT ERROR --- //
+
+#[derive(thiserror::Error)]
+pub enum DraftError {
+    #[error("There is no subscriber with this email address.")]
+    UnknownSubscriber,
+    #[error("{0}")]
+    InvalidRecipients(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for DraftError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for DraftError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DraftError::UnknownSubscriber => StatusCode::NOT_FOUND,
+            DraftError::InvalidRecipients(_) => StatusCode::BAD_REQUEST,
+            DraftError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            DraftError::UnknownSubscriber => Problem::new(
+                self.status_code(),
+                "unknown-subscriber",
+                "Unknown subscriber",
+                self.to_string(),
+            )
+            .response(),
+            DraftError::InvalidRecipients(_) => Problem::new(
+                self.status_code(),
+                "invalid-recipients",
+                "Invalid recipients",
+                self.to_string(),
+            )
+            .response(),
+            DraftError::UnexpectedError(_) => Problem::internal_error().response(),
+        }
+    }
+}
+
+// --- RATE LIMIT ERROR --- //
+
+#[derive(thiserror::Error)]
+#[error("Too many requests. Retry in {} second(s).", self.retry_after_seconds())]
+pub struct RateLimitError {
+    pub retry_after: Duration,
+}
+
+impl RateLimitError {
+    /// `Retry-After` only takes whole seconds, so round up.
+    pub fn retry_after_seconds(&self) -> u64 {
+        self.retry_after.as_secs_f64().ceil().max(1.0) as u64
+    }
+}
+
+impl Debug for RateLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for RateLimitError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = Problem::new(
+            self.status_code(),
+            "rate-limited",
+            "Too many requests",
+            self.to_string(),
+        )
+        .response();
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(self.retry_after_seconds()));
+        response
+    }
+}
+
+// --- ADMIN AUTHENTICATION ERROR --- //
+
+#[derive(thiserror::Error)]
+pub enum AdminAuthError {
+    #[error("The admin endpoints are disabled because no admin token is configured.")]
+    Disabled,
+    #[error("Missing admin bearer token.")]
+    MissingCredentials,
+    #[error("Invalid admin bearer token.")]
+    InvalidCredentials,
+}
+
+impl Debug for AdminAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AdminAuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AdminAuthError::Disabled => StatusCode::FORBIDDEN,
+            AdminAuthError::MissingCredentials | AdminAuthError::InvalidCredentials => {
+                StatusCode::UNAUTHORIZED
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let (problem_type, title) = match self {
+            AdminAuthError::Disabled => ("admin-disabled", "Admin endpoints disabled"),
+            AdminAuthError::MissingCredentials => ("missing-credentials", "Missing credentials"),
+            AdminAuthError::InvalidCredentials => ("invalid-credentials", "Invalid credentials"),
+        };
+        let mut response =
+            Problem::new(self.status_code(), problem_type, title, self.to_string()).response();
+        if self.status_code() == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+// --- LOG FILTER ERROR --- //
+
+#[derive(thiserror::Error)]
+pub enum LogFilterError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for LogFilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for LogFilterError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            LogFilterError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            LogFilterError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            LogFilterError::ValidationError(_) => Problem::new(
+                self.status_code(),
+                "invalid-log-filter",
+                "Invalid log filter",
+                self.to_string(),
+            )
+            .response(),
+            LogFilterError::UnexpectedError(_) => Problem::internal_error().response(),
+        }
+    }
 }
 
 // --- HELPERS --- //
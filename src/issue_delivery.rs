@@ -0,0 +1,417 @@
+use anyhow::Context;
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    domain::{EmailFormat, SubscriberEmail},
+    email_client::EmailClient,
+    subscription_tokens::{SubscriberLinks, TokenHasher},
+};
+
+/// The content of a stored newsletter issue.
+pub struct NewsletterIssue {
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+}
+
+/// An issue as one subscriber receives it.
+#[derive(Serialize, Debug)]
+pub struct RenderedIssue {
+    pub subject: String,
+    /// Empty for subscribers who only want plain text.
+    pub html_content: String,
+    pub text_content: String,
+}
+
+/// Outcome of a delivery run for a single issue.
+#[derive(Debug, Default)]
+pub struct DeliveryReport {
+    pub sent: usize,
+    pub failed: usize,
+}
+
+/// Longest slug made from a title, before the suffix that keeps it unique.
+const MAX_SLUG_LENGTH: usize = 80;
+
+/// Store an issue. Its slug is made from the title, with a suffix if another
+/// issue already has the same one. Concurrent publishes with the same title
+/// settle on the slug constraint, so neither fails.
+#[tracing::instrument(name = "Storing newsletter issue", skip_all)]
+pub async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    topic: Option<&str>,
+    segment: Option<&str>,
+    public: bool,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let slug = slugify(title);
+    let suffixed = format!("{slug}-{}", &newsletter_issue_id.simple().to_string()[..8]);
+    for slug in [slug, suffixed] {
+        let inserted = sqlx::query_scalar!(
+            r#"
+            INSERT INTO newsletter_issues (
+                newsletter_issue_id,
+                title,
+                text_content,
+                html_content,
+                published_at,
+                topic,
+                segment,
+                public,
+                slug
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (slug) DO NOTHING
+            RETURNING newsletter_issue_id
+            "#,
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            Utc::now(),
+            topic,
+            segment,
+            public,
+            slug
+        )
+        .fetch_optional(&mut **transaction)
+        .await?;
+        if inserted.is_some() {
+            return Ok(newsletter_issue_id);
+        }
+    }
+    // The suffix comes from the random id: only another issue with the same
+    // title and id prefix can hold it.
+    Err(sqlx::Error::Protocol(format!(
+        "Every slug for `{title}` is taken."
+    )))
+}
+
+/// The lowercase letters and digits of `title`, words separated by dashes.
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::with_capacity(title.len());
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() {
+            if slug.len() == MAX_SLUG_LENGTH {
+                break;
+            }
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "issue".into()
+    } else {
+        slug.into()
+    }
+}
+
+#[tracing::instrument(name = "Enqueuing issue delivery", skip(transaction, subscriber_email))]
+pub async fn enqueue_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    subscriber_email: &SubscriberEmail,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_email, status, updated_at)
+        VALUES ($1, $2, 'pending', $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        subscriber_email.as_ref(),
+        Utc::now()
+    );
+
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Retrieving newsletter issue", skip(db_pool))]
+pub async fn get_issue(
+    db_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(db_pool)
+    .await
+}
+
+/// Send every pending delivery of an issue, recording the outcome of each attempt.
+///
+/// Each delivery is claimed with `FOR UPDATE SKIP LOCKED`, so concurrent runs
+/// (e.g. the API and an operator) never send the same email twice. Every email
+/// ends with a link to the preferences of its recipient, and goes out in the
+/// format they picked there.
+#[tracing::instrument(
+    name = "Delivering newsletter issue",
+    skip(db_pool, email_client, base_url, token_hasher)
+)]
+pub async fn deliver_issue(
+    db_pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    token_hasher: &TokenHasher,
+    newsletter_issue_id: Uuid,
+) -> Result<DeliveryReport, anyhow::Error> {
+    let issue = get_issue(db_pool, newsletter_issue_id)
+        .await
+        .context("Failed to retrieve newsletter issue.")?
+        .with_context(|| format!("Newsletter issue {newsletter_issue_id} does not exist."))?;
+
+    let mut report = DeliveryReport::default();
+    while let Some((mut transaction, delivery)) = dequeue_delivery(db_pool, newsletter_issue_id)
+        .await
+        .context("Failed to dequeue a pending delivery.")?
+    {
+        let email = delivery.subscriber_email;
+        let links = delivery
+            .subscriber_id
+            .map(|subscriber_id| SubscriberLinks::new(base_url, token_hasher, subscriber_id));
+        let email_format = delivery
+            .email_format
+            .as_deref()
+            .and_then(|format| format.parse().ok())
+            .unwrap_or_default();
+        let rendered = render_issue(&issue, links.as_ref(), email_format);
+        let outcome = match SubscriberEmail::parse(email.clone()) {
+            Ok(recipient) => email_client
+                .send_email(
+                    &recipient,
+                    &rendered.subject,
+                    &rendered.html_content,
+                    &rendered.text_content,
+                )
+                .await
+                .map_err(anyhow::Error::from),
+            Err(error) => Err(anyhow::anyhow!(error)),
+        };
+
+        let last_error = match &outcome {
+            Ok(()) => {
+                report.sent += 1;
+                None
+            }
+            Err(error) => {
+                report.failed += 1;
+                tracing::error!(
+                    error.cause_chain = ?error,
+                    error.message = %error,
+                    "Failed to deliver issue to a confirmed subscriber."
+                );
+                Some(format!("{error:#}"))
+            }
+        };
+
+        record_delivery_attempt(&mut transaction, newsletter_issue_id, &email, last_error)
+            .await
+            .context("Failed to record a delivery attempt.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to record a delivery attempt.")?;
+    }
+
+    Ok(report)
+}
+
+/// Render `issue` in `email_format`, ending with links to the preferences of
+/// the recipient and to unsubscribe if they are a subscriber.
+pub fn render_issue(
+    issue: &NewsletterIssue,
+    links: Option<&SubscriberLinks>,
+    email_format: EmailFormat,
+) -> RenderedIssue {
+    let (html_content, text_content) = match links {
+        Some(links) => (
+            format!("{}{}", issue.html_content, links.html_footer()),
+            format!("{}\n\n{}", issue.text_content, links.text_footer()),
+        ),
+        None => (issue.html_content.clone(), issue.text_content.clone()),
+    };
+    RenderedIssue {
+        subject: issue.title.clone(),
+        html_content: match email_format {
+            EmailFormat::Html => html_content,
+            EmailFormat::PlainText => String::new(),
+        },
+        text_content,
+    }
+}
+
+/// Mark the failed deliveries of an issue as pending again and deliver them.
+#[tracing::instrument(
+    name = "Retrying failed issue deliveries",
+    skip(db_pool, email_client, base_url, token_hasher)
+)]
+pub async fn retry_failed_deliveries(
+    db_pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    token_hasher: &TokenHasher,
+    newsletter_issue_id: Uuid,
+) -> Result<DeliveryReport, anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET status = 'pending', updated_at = $2
+        WHERE newsletter_issue_id = $1 AND status = 'failed'
+        "#,
+        newsletter_issue_id,
+        Utc::now()
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to reset failed deliveries.")?;
+
+    deliver_issue(
+        db_pool,
+        email_client,
+        base_url,
+        token_hasher,
+        newsletter_issue_id,
+    )
+    .await
+}
+
+/// A pending delivery, with the preferences of its recipient as of now. They
+/// are missing if the subscriber was deleted since the issue was published.
+struct PendingDelivery {
+    subscriber_email: String,
+    subscriber_id: Option<Uuid>,
+    email_format: Option<String>,
+}
+
+async fn dequeue_delivery(
+    db_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<(Transaction<'static, Postgres>, PendingDelivery)>, sqlx::Error> {
+    let mut transaction = db_pool.begin().await?;
+    let delivery = sqlx::query_as!(
+        PendingDelivery,
+        r#"
+        SELECT d.subscriber_email, s.id AS "subscriber_id?", s.email_format AS "email_format?"
+        FROM issue_deliveries d
+        LEFT JOIN subscriptions s ON s.email = d.subscriber_email
+        WHERE d.newsletter_issue_id = $1 AND d.status = 'pending'
+        FOR UPDATE OF d
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    Ok(delivery.map(|delivery| (transaction, delivery)))
+}
+
+async fn record_delivery_attempt(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    subscriber_email: &str,
+    last_error: Option<String>,
+) -> Result<(), sqlx::Error> {
+    let status = if last_error.is_some() {
+        "failed"
+    } else {
+        "sent"
+    };
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET status = $3, attempts = attempts + 1, last_error = $4, updated_at = $5
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        newsletter_issue_id,
+        subscriber_email,
+        status,
+        last_error,
+        Utc::now()
+    );
+
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_issue, slugify, NewsletterIssue, MAX_SLUG_LENGTH};
+    use crate::{domain::EmailFormat, subscription_tokens::SubscriberLinks};
+
+    fn issue() -> NewsletterIssue {
+        NewsletterIssue {
+            title: "Title".into(),
+            text_content: "Body".into(),
+            html_content: "<p>Body</p>".into(),
+        }
+    }
+
+    fn links() -> SubscriberLinks {
+        SubscriberLinks {
+            preferences: "https://example.com/preferences?token=t".into(),
+            unsubscribe: "https://example.com/unsubscribe?token=t".into(),
+        }
+    }
+
+    #[test]
+    fn subscribers_get_links_to_their_preferences_and_to_unsubscribe() {
+        let rendered = render_issue(&issue(), Some(&links()), EmailFormat::Html);
+
+        assert_eq!(rendered.subject, "Title");
+        assert_eq!(
+            rendered.html_content,
+            r#"<p>Body</p><p><a href="https://example.com/preferences?token=t">Manage your preferences</a> | <a href="https://example.com/unsubscribe?token=t">Unsubscribe</a></p>"#
+        );
+        assert_eq!(
+            rendered.text_content,
+            "Body\n\nManage your preferences: https://example.com/preferences?token=t\nUnsubscribe: https://example.com/unsubscribe?token=t"
+        );
+    }
+
+    #[test]
+    fn plain_text_subscribers_get_no_html() {
+        let rendered = render_issue(&issue(), None, EmailFormat::PlainText);
+
+        assert_eq!(rendered.html_content, "");
+        assert_eq!(rendered.text_content, "Body");
+    }
+
+    #[test]
+    fn slugs_keep_lowercase_words() {
+        assert_eq!(
+            slugify("Release notes: v2.0 (final!)"),
+            "release-notes-v2-0-final"
+        );
+        assert_eq!(slugify("  --Hello,   World--  "), "hello-world");
+    }
+
+    #[test]
+    fn titles_without_letters_or_digits_get_a_default_slug() {
+        assert_eq!(slugify("¡¿…?!"), "issue");
+    }
+
+    #[test]
+    fn slugs_are_cut_to_a_maximum_length() {
+        let slug = slugify(&"word ".repeat(100));
+
+        assert!(slug.len() <= MAX_SLUG_LENGTH);
+        assert!(!slug.ends_with('-'));
+    }
+}
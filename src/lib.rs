@@ -1,7 +1,19 @@
+pub mod authentication;
+pub mod bot_protection;
+pub mod cli;
 pub mod configuration;
+pub mod deliverability;
+pub mod digest;
 pub mod domain;
 pub mod email_client;
+pub mod email_outbox;
 pub mod errors;
+pub mod issue_delivery;
+pub mod migrations;
+pub mod problem;
+pub mod rate_limit;
 pub mod routes;
+pub mod segment;
 pub mod startup;
+pub mod subscription_tokens;
 pub mod telemetry;
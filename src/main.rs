@@ -1,18 +1,40 @@
+use clap::Parser;
 use zero2prod::{
+    cli::{self, Cli, Command},
     configuration::get_configuration,
-    startup::Application,
     telemetry::{get_subscriber, init_subscriber},
 };
 
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
-    // Handle telemetry
-    let subscriber = get_subscriber("zero2prod", "info", std::io::stdout);
-    init_subscriber(subscriber);
+async fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
+    let format = cli.output_format();
+    let command = cli.command.unwrap_or(Command::Serve);
 
-    // Get configuration settings for database and application host address
-    let configuration = get_configuration().expect("Failed to read configuration.");
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
-    Ok(())
+    // Handle telemetry. Only the server logs to stdout; operator commands keep
+    // it free for their own output and only report warnings.
+    let log_filter = if let Command::Serve = command {
+        let (subscriber, log_filter) = get_subscriber("zero2prod", "info", std::io::stdout);
+        init_subscriber(subscriber);
+        log_filter
+    } else {
+        let (subscriber, log_filter) = get_subscriber("zero2prod", "warn", std::io::stderr);
+        init_subscriber(subscriber);
+        log_filter
+    };
+
+    if let Command::Config { command } = command {
+        return cli::config::run(command, format).await;
+    }
+
+    // Get configuration settings for database and application host address.
+    // Report every configuration problem at once instead of panicking later.
+    let configuration = match get_configuration() {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    cli::run(command, configuration, format, log_filter).await
 }
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use sqlx::{
+    migrate::{Migrate, MigrateError, Migrator},
+    PgPool,
+};
+
+/// The migrations under `migrations/`, embedded into the binary at compile time
+/// so that deployments do not depend on `sqlx-cli` being installed.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Whether an embedded migration has been applied to the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// The migration was applied but its contents changed since.
+    ChecksumMismatch,
+}
+
+impl MigrationState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MigrationState::Applied => "applied",
+            MigrationState::Pending => "pending",
+            MigrationState::ChecksumMismatch => "checksum mismatch",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+/// Apply every pending migration.
+///
+/// The migrator holds a Postgres advisory lock for the duration of the run, so
+/// concurrent runners (e.g. several instances booting with `auto_migrate`)
+/// wait for each other instead of racing.
+#[tracing::instrument(name = "Running database migrations", skip(db_pool))]
+pub async fn run_migrations(db_pool: &PgPool) -> Result<(), MigrateError> {
+    MIGRATOR.run(db_pool).await
+}
+
+/// List the embedded migrations alongside their state in the database.
+#[tracing::instrument(name = "Retrieving migration status", skip(db_pool))]
+pub async fn migration_status(db_pool: &PgPool) -> Result<Vec<MigrationStatus>, MigrateError> {
+    let mut connection = db_pool.acquire().await?;
+    connection.ensure_migrations_table().await?;
+    let applied: HashMap<_, _> = connection
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| (m.version, m.checksum))
+        .collect();
+
+    let status = MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| {
+            let state = match applied.get(&m.version) {
+                None => MigrationState::Pending,
+                Some(checksum) if *checksum != m.checksum => MigrationState::ChecksumMismatch,
+                Some(_) => MigrationState::Applied,
+            };
+            MigrationStatus {
+                version: m.version,
+                description: m.description.to_string(),
+                state,
+            }
+        })
+        .collect();
+
+    Ok(status)
+}
+
+/// Revert the most recently applied migration, returning its version.
+///
+/// Only reversible migrations (a `.up.sql`/`.down.sql` pair) can be reverted.
+/// Returns `Ok(None)` if no migration has been applied yet.
+#[tracing::instrument(name = "Reverting the last database migration", skip(db_pool))]
+pub async fn revert_last_migration(db_pool: &PgPool) -> Result<Option<i64>, anyhow::Error> {
+    let mut connection = db_pool.acquire().await?;
+    connection.ensure_migrations_table().await?;
+    let mut applied: Vec<i64> = connection
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| m.version)
+        .collect();
+    applied.sort_unstable();
+
+    let Some(last) = applied.pop() else {
+        return Ok(None);
+    };
+
+    let is_reversible = MIGRATOR
+        .iter()
+        .any(|m| m.version == last && m.migration_type.is_down_migration());
+    if !is_reversible {
+        anyhow::bail!("Migration {last} has no down migration and cannot be reverted.");
+    }
+
+    // `undo` takes the same advisory lock as `run`.
+    let target = applied.last().copied().unwrap_or(0);
+    MIGRATOR.undo(&mut *connection, target).await?;
+    Ok(Some(last))
+}
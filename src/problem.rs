@@ -0,0 +1,144 @@
+use std::fmt::Display;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::{header::CONTENT_TYPE, StatusCode},
+    middleware::Next,
+    web, HttpMessage, HttpResponse,
+};
+use serde::Serialize;
+use tracing_actix_web::RequestId;
+
+tokio::task_local! {
+    /// The id of the request being handled, see [`correlate`].
+    static CORRELATION_ID: String;
+}
+
+/// An error response in the format of RFC 7807, served as
+/// `application/problem+json`.
+///
+/// `detail` is shown to users: it must not leak internals. The full error
+/// chain only goes to the logs.
+#[derive(Serialize, Debug)]
+pub struct Problem {
+    /// A stable, machine-readable code, e.g. `invalid-email`.
+    #[serde(rename = "type")]
+    pub problem_type: &'static str,
+    pub title: &'static str,
+    pub status: u16,
+    pub detail: String,
+    /// The `request_id` of the request span, to find the matching logs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Every invalid field of the request, for forms to highlight them all.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub invalid_params: Vec<InvalidParam>,
+}
+
+/// A problem with one field of a request, as in the `invalid-params`
+/// example of RFC 7807.
+#[derive(Serialize, Debug)]
+pub struct InvalidParam {
+    pub name: &'static str,
+    /// A stable, machine-readable code, e.g. `disposable-email`.
+    #[serde(rename = "type")]
+    pub problem_type: &'static str,
+    pub reason: String,
+}
+
+impl Problem {
+    pub fn new(
+        status: StatusCode,
+        problem_type: &'static str,
+        title: &'static str,
+        detail: impl Into<String>,
+    ) -> Self {
+        Self {
+            problem_type,
+            title,
+            status: status.as_u16(),
+            detail: detail.into(),
+            correlation_id: CORRELATION_ID.try_with(Clone::clone).ok(),
+            invalid_params: Vec::new(),
+        }
+    }
+
+    pub fn with_invalid_params(mut self, invalid_params: Vec<InvalidParam>) -> Self {
+        self.invalid_params = invalid_params;
+        self
+    }
+
+    /// For errors we do not want to describe to users.
+    pub fn internal_error() -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal-error",
+            "Internal server error",
+            "Something went wrong on our side. Please mention the correlation id if you contact us.",
+        )
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .insert_header((CONTENT_TYPE, "application/problem+json"))
+            .body(serde_json::to_string(self).expect("Problems serialize to JSON"))
+    }
+}
+
+/// Make the request id available to [`Problem::new`] while the request is
+/// handled, so that error responses carry it.
+///
+/// Must be wrapped inside `TracingLogger`, which assigns the id.
+pub async fn correlate(
+    request: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let correlation_id = request
+        .extensions()
+        .get::<RequestId>()
+        .map(ToString::to_string)
+        .unwrap_or_default();
+    CORRELATION_ID
+        .scope(correlation_id, async move {
+            next.call(request).await.map_err(|e| {
+                // Render errors from inner middleware while the id is still
+                // in scope, keeping the error itself for `TracingLogger`.
+                let response = e.error_response();
+                InternalError::from_response(e, response).into()
+            })
+        })
+        .await
+}
+
+/// Report bodies and query strings that cannot be deserialized as problems
+/// too.
+pub fn form_config() -> web::FormConfig {
+    web::FormConfig::default().error_handler(|e, _| malformed_request(e))
+}
+
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|e, _| malformed_request(e))
+}
+
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|e, _| malformed_request(e))
+}
+
+fn malformed_request<E>(e: E) -> actix_web::Error
+where
+    E: Display + std::fmt::Debug + 'static,
+{
+    let problem = Problem::new(
+        StatusCode::BAD_REQUEST,
+        "malformed-request",
+        "Malformed request",
+        e.to_string(),
+    );
+    InternalError::from_response(e, problem.response()).into()
+}
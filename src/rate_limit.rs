@@ -0,0 +1,421 @@
+use std::{collections::HashMap, net::IpAddr, sync::Mutex, time::Duration};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::HeaderMap,
+    middleware::Next,
+    web, HttpRequest,
+};
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use rand::Rng;
+use sqlx::PgPool;
+
+use crate::{
+    configuration::{RateLimit, RateLimitSettings, RateLimitStoreKind, SharedRuntimeSettings},
+    errors::RateLimitError,
+};
+
+/// Routes whose limits can be configured under `rate_limit.routes`. Each is
+/// the name of an actix resource.
+pub const RATE_LIMITED_ROUTES: &[&str] = &["subscribe", "resend_confirmation"];
+
+/// Buckets left alone for this long are dropped. They would have refilled
+/// for any sensible limit anyway.
+const STALE_BUCKET_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+/// How often the in-memory store drops stale buckets.
+const MEMORY_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+/// The most buckets the in-memory store holds. Past this, the least recently
+/// used tenth is evicted to make room: those keys start over with a full
+/// bucket.
+const MAX_MEMORY_BUCKETS: usize = 100_000;
+
+/// What a request is counted against.
+pub enum RateLimitKey<'a> {
+    Ip(IpAddr),
+    Email(&'a str),
+}
+
+/// Token buckets for the limits in [`RateLimitSettings`].
+pub struct RateLimiter {
+    store: RateLimitStore,
+}
+
+enum RateLimitStore {
+    Memory(Mutex<MemoryBuckets>),
+    /// Shared between every instance, see the `rate_limit_buckets` table.
+    Postgres(PgPool),
+}
+
+impl RateLimiter {
+    pub fn new(kind: RateLimitStoreKind, db_pool: PgPool) -> Self {
+        let store = match kind {
+            RateLimitStoreKind::Memory => {
+                RateLimitStore::Memory(Mutex::new(MemoryBuckets::new(MAX_MEMORY_BUCKETS)))
+            }
+            RateLimitStoreKind::Postgres => RateLimitStore::Postgres(db_pool),
+        };
+        Self { store }
+    }
+
+    /// Take a token from the bucket of `key` for `route`, if the route has a
+    /// limit for that kind of key.
+    ///
+    /// If the store cannot be reached the request is let through: an outage
+    /// of the rate limiter should not take subscriptions down with it.
+    pub async fn check(
+        &self,
+        settings: &RateLimitSettings,
+        route: &str,
+        key: RateLimitKey<'_>,
+    ) -> Result<(), RateLimitError> {
+        let Some(limits) = settings.routes.get(route) else {
+            return Ok(());
+        };
+        let (limit, key) = match key {
+            RateLimitKey::Ip(ip) => (limits.per_ip, format!("{route}:ip:{ip}")),
+            RateLimitKey::Email(email) => (
+                limits.per_email,
+                format!("{route}:email:{}", email.to_lowercase()),
+            ),
+        };
+        let Some(limit) = limit else {
+            return Ok(());
+        };
+
+        let outcome = match &self.store {
+            RateLimitStore::Memory(buckets) => {
+                Ok(buckets.lock().unwrap().take(&key, &limit, Utc::now()))
+            }
+            RateLimitStore::Postgres(db_pool) => take_from_postgres(db_pool, &key, &limit).await,
+        };
+        match outcome {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(retry_after)) => {
+                tracing::warn!(key, "Rate limit exceeded.");
+                Err(RateLimitError { retry_after })
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to check the rate limit, letting the request through."
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Limit requests by client address on the routes listed in
+/// `rate_limit.routes`, identified by their resource name.
+pub async fn rate_limit_by_ip(
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if let (Some(route), Some(limiter), Some(settings)) = (
+        request.match_name(),
+        request.app_data::<web::Data<RateLimiter>>(),
+        request.app_data::<web::Data<SharedRuntimeSettings>>(),
+    ) {
+        let settings = settings.current();
+        if let Some(ip) = client_ip(request.request(), &settings.rate_limit.trusted_proxies) {
+            limiter
+                .check(&settings.rate_limit, route, RateLimitKey::Ip(ip))
+                .await?;
+        }
+    }
+    next.call(request).await
+}
+
+/// The address of the client, as reported by `X-Forwarded-For` if the
+/// request came through trusted proxies.
+pub fn client_ip(request: &HttpRequest, trusted_proxies: &[IpNet]) -> Option<IpAddr> {
+    let peer = request.peer_addr()?.ip();
+    Some(resolve_client_ip(
+        peer,
+        &forwarded_for(request.headers()),
+        trusted_proxies,
+    ))
+}
+
+fn forwarded_for(headers: &HeaderMap) -> String {
+    headers
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Walk `X-Forwarded-For` from the nearest hop outwards and stop at the first
+/// address we do not trust: anything further left could have been forged.
+fn resolve_client_ip(peer: IpAddr, forwarded_for: &str, trusted_proxies: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|network| network.contains(ip));
+
+    let mut client = peer;
+    if !is_trusted(&client) {
+        return client;
+    }
+    for hop in forwarded_for.rsplit(',').map(str::trim) {
+        match hop.parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !is_trusted(&client) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: DateTime<Utc>,
+}
+
+impl Bucket {
+    fn full(limit: &RateLimit, now: DateTime<Utc>) -> Self {
+        Self {
+            tokens: f64::from(limit.requests),
+            updated_at: now,
+        }
+    }
+
+    /// Refill the bucket for the time elapsed since it was last used, then
+    /// take a token. Fails with how long until a token is available.
+    fn take(&mut self, limit: &RateLimit, now: DateTime<Utc>) -> Result<(), Duration> {
+        let rate = f64::from(limit.requests) / limit.per_seconds as f64;
+        let elapsed = (now - self.updated_at).to_std().unwrap_or_default();
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(f64::from(limit.requests));
+        self.updated_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+/// The buckets of the in-memory store, with at most `capacity` of them.
+struct MemoryBuckets {
+    buckets: HashMap<String, Bucket>,
+    capacity: usize,
+    pruned_at: DateTime<Utc>,
+}
+
+impl MemoryBuckets {
+    fn new(capacity: usize) -> Self {
+        Self {
+            buckets: HashMap::new(),
+            capacity,
+            pruned_at: Utc::now(),
+        }
+    }
+
+    fn take(&mut self, key: &str, limit: &RateLimit, now: DateTime<Utc>) -> Result<(), Duration> {
+        let age = |bucket: &Bucket| (now - bucket.updated_at).to_std().unwrap_or_default();
+        if (now - self.pruned_at).to_std().unwrap_or_default() >= MEMORY_PRUNE_INTERVAL {
+            self.buckets
+                .retain(|_, bucket| age(bucket) < STALE_BUCKET_AGE);
+            self.pruned_at = now;
+        }
+        if self.buckets.len() >= self.capacity && !self.buckets.contains_key(key) {
+            self.evict_least_recently_used((self.capacity / 10).max(1));
+        }
+        self.buckets
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket::full(limit, now))
+            .take(limit, now)
+    }
+
+    /// Drop at least `count` buckets, the ones left alone the longest, in a
+    /// single pass.
+    fn evict_least_recently_used(&mut self, count: usize) {
+        let mut updated_at: Vec<_> = self.buckets.values().map(|b| b.updated_at).collect();
+        let Some(index) = count.checked_sub(1).filter(|i| *i < updated_at.len()) else {
+            self.buckets.clear();
+            return;
+        };
+        let (_, cutoff, _) = updated_at.select_nth_unstable(index);
+        let cutoff = *cutoff;
+        self.buckets.retain(|_, bucket| bucket.updated_at > cutoff);
+    }
+}
+
+#[tracing::instrument(name = "Taking a rate limit token from Postgres", skip(db_pool, limit))]
+async fn take_from_postgres(
+    db_pool: &PgPool,
+    key: &str,
+    limit: &RateLimit,
+) -> Result<Result<(), Duration>, sqlx::Error> {
+    let now = Utc::now();
+    let mut tx = db_pool.begin().await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO rate_limit_buckets (key, tokens, updated_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (key) DO NOTHING
+        "#,
+        key,
+        f64::from(limit.requests),
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+    let row = sqlx::query!(
+        "SELECT tokens, updated_at FROM rate_limit_buckets WHERE key = $1 FOR UPDATE",
+        key
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let mut bucket = Bucket {
+        tokens: row.tokens,
+        updated_at: row.updated_at,
+    };
+    let outcome = bucket.take(limit, now);
+    sqlx::query!(
+        "UPDATE rate_limit_buckets SET tokens = $2, updated_at = $3 WHERE key = $1",
+        key,
+        bucket.tokens,
+        bucket.updated_at
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    // Every so often, clear out buckets nobody has used in a while.
+    if rand::thread_rng().gen_ratio(1, 1000) {
+        sqlx::query!(
+            "DELETE FROM rate_limit_buckets WHERE updated_at < $1",
+            now - STALE_BUCKET_AGE
+        )
+        .execute(db_pool)
+        .await?;
+    }
+
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, time::Duration};
+
+    use chrono::Utc;
+    use claims::{assert_err, assert_ok};
+    use ipnet::IpNet;
+
+    use super::{resolve_client_ip, Bucket, MemoryBuckets, STALE_BUCKET_AGE};
+    use crate::configuration::RateLimit;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn networks(networks: &[&str]) -> Vec<IpNet> {
+        networks.iter().map(|n| n.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn bucket_allows_bursts_up_to_its_capacity() {
+        let limit = RateLimit {
+            requests: 3,
+            per_seconds: 60,
+        };
+        let now = Utc::now();
+        let mut bucket = Bucket::full(&limit, now);
+
+        for _ in 0..3 {
+            assert_ok!(bucket.take(&limit, now));
+        }
+        let retry_after = bucket.take(&limit, now).unwrap_err();
+
+        assert_eq!(retry_after, Duration::from_secs(20));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let limit = RateLimit {
+            requests: 1,
+            per_seconds: 10,
+        };
+        let now = Utc::now();
+        let mut bucket = Bucket::full(&limit, now);
+        assert_ok!(bucket.take(&limit, now));
+
+        assert_err!(bucket.take(&limit, now + Duration::from_secs(5)));
+        assert_ok!(bucket.take(&limit, now + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn the_memory_store_evicts_the_least_recently_used_buckets_when_full() {
+        let limit = RateLimit {
+            requests: 1,
+            per_seconds: 60,
+        };
+        let start = Utc::now();
+        let mut buckets = MemoryBuckets::new(10);
+        for i in 0..10 {
+            let now = start + chrono::Duration::seconds(i);
+            assert_ok!(buckets.take(&format!("key-{i}"), &limit, now));
+        }
+
+        let now = start + chrono::Duration::seconds(10);
+        assert_ok!(buckets.take("key-10", &limit, now));
+
+        assert_eq!(buckets.buckets.len(), 10);
+        assert!(!buckets.buckets.contains_key("key-0"));
+        assert_err!(buckets.take("key-9", &limit, now));
+    }
+
+    #[test]
+    fn the_memory_store_drops_stale_buckets_from_time_to_time() {
+        let limit = RateLimit {
+            requests: 1,
+            per_seconds: 60,
+        };
+        let start = Utc::now();
+        let mut buckets = MemoryBuckets::new(10);
+        assert_ok!(buckets.take("stale", &limit, start));
+
+        let later = start + chrono::Duration::from_std(STALE_BUCKET_AGE).unwrap();
+        assert_ok!(buckets.take("fresh", &limit, later));
+
+        assert_eq!(buckets.buckets.len(), 1);
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let client = resolve_client_ip(ip("203.0.113.7"), "198.51.100.1", &[]);
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn forwarded_for_is_followed_through_trusted_proxies_only() {
+        let trusted = networks(&["10.0.0.0/8"]);
+
+        let client = resolve_client_ip(
+            ip("10.0.0.1"),
+            "198.51.100.1, 203.0.113.7, 10.0.0.2",
+            &trusted,
+        );
+
+        // `198.51.100.1` was reported by `203.0.113.7`, which we do not trust.
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn garbage_in_forwarded_for_stops_the_walk() {
+        let trusted = networks(&["10.0.0.0/8"]);
+
+        let client = resolve_client_ip(ip("10.0.0.1"), "198.51.100.1, not-an-ip", &trusted);
+
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+
+use crate::{authentication::Admin, errors::LogFilterError, telemetry::LogFilter};
+
+/// How long a temporary log filter lasts unless the request says otherwise.
+const DEFAULT_LOG_FILTER_TTL: Duration = Duration::from_secs(5 * 60);
+const MAX_LOG_FILTER_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Deserialize)]
+pub struct LogFilterData {
+    /// `EnvFilter` directives, e.g. `info,sqlx=debug`.
+    directives: String,
+    ttl_seconds: Option<u64>,
+}
+
+pub async fn get_log_filter(_admin: Admin, log_filter: web::Data<LogFilter>) -> HttpResponse {
+    HttpResponse::Ok().json(log_filter.status())
+}
+
+#[tracing::instrument(
+    name = "Changing the log filter temporarily",
+    skip(_admin, body, log_filter),
+    fields(directives = %body.directives, ttl_seconds = ?body.ttl_seconds)
+)]
+pub async fn put_log_filter(
+    _admin: Admin,
+    body: web::Json<LogFilterData>,
+    log_filter: web::Data<LogFilter>,
+) -> Result<HttpResponse, LogFilterError> {
+    let ttl = body
+        .ttl_seconds
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_LOG_FILTER_TTL);
+    if ttl.is_zero() || ttl > MAX_LOG_FILTER_TTL {
+        return Err(LogFilterError::ValidationError(format!(
+            "ttl_seconds must be between 1 and {}.",
+            MAX_LOG_FILTER_TTL.as_secs()
+        )));
+    }
+    let status = log_filter
+        .set_temporarily(&body.directives, ttl)
+        .map_err(LogFilterError::ValidationError)?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[tracing::instrument(name = "Resetting the log filter", skip(_admin, log_filter))]
+pub async fn delete_log_filter(
+    _admin: Admin,
+    log_filter: web::Data<LogFilter>,
+) -> Result<HttpResponse, LogFilterError> {
+    let status = log_filter.reset().map_err(anyhow::Error::msg)?;
+    Ok(HttpResponse::Ok().json(status))
+}
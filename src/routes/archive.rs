@@ -0,0 +1,295 @@
+use actix_web::{http::StatusCode, web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    configuration::ArchiveSettings,
+    errors::ArchiveError,
+    routes::pages::{escape_html, landing_page},
+    startup::ApplicationBaseUrl,
+};
+
+/// A public issue, as the archive and the feeds show it.
+struct ArchivedIssue {
+    newsletter_issue_id: Uuid,
+    slug: String,
+    title: String,
+    html_content: String,
+    text_content: String,
+    published_at: DateTime<Utc>,
+}
+
+impl ArchivedIssue {
+    fn url(&self, base_url: &str) -> String {
+        format!("{base_url}/archive/{}", self.slug)
+    }
+
+    /// Stays the same if the slug or the base URL change.
+    fn feed_id(&self) -> String {
+        format!("urn:uuid:{}", self.newsletter_issue_id)
+    }
+}
+
+/// The body of `/feed.json`, see <https://www.jsonfeed.org/version/1.1/>.
+#[derive(Serialize)]
+pub struct JsonFeed<'a> {
+    pub version: &'static str,
+    pub title: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub description: &'a str,
+    pub home_page_url: String,
+    pub feed_url: String,
+    pub items: Vec<JsonFeedItem<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct JsonFeedItem<'a> {
+    pub id: String,
+    pub url: String,
+    pub title: &'a str,
+    pub content_html: &'a str,
+    pub content_text: &'a str,
+    pub date_published: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Showing the archive", skip_all)]
+pub async fn archive(
+    pool: web::Data<PgPool>,
+    settings: web::Data<ArchiveSettings>,
+) -> Result<HttpResponse, ArchiveError> {
+    let issues = get_public_issues(&pool, None).await?;
+    let list = if issues.is_empty() {
+        "<p>Nothing has been published yet.</p>".to_string()
+    } else {
+        let items: String = issues
+            .iter()
+            .map(|issue| {
+                format!(
+                    r#"
+        <li><a href="/archive/{slug}">{title}</a> <small>{date}</small></li>"#,
+                    slug = escape_html(&issue.slug),
+                    title = escape_html(&issue.title),
+                    date = issue.published_at.format("%B %-d, %Y"),
+                )
+            })
+            .collect();
+        format!("<ul>{items}\n    </ul>")
+    };
+    let content = format!(
+        r#"{list}
+    <p>Follow along with <a href="/feed.xml">Atom</a>, <a href="/rss.xml">RSS</a> or <a href="/feed.json">JSON Feed</a>.</p>"#
+    );
+    Ok(landing_page(
+        StatusCode::OK,
+        &escape_html(&settings.title),
+        &content,
+    ))
+}
+
+#[tracing::instrument(name = "Showing an archived issue", skip(pool))]
+pub async fn archived_issue(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ArchiveError> {
+    let Some(issue) = get_public_issue(&pool, &slug).await? else {
+        return Ok(landing_page(
+            StatusCode::NOT_FOUND,
+            "This issue does not exist",
+            r#"<p>Have a look at the <a href="/archive">archive</a> instead.</p>"#,
+        ));
+    };
+    let content = format!(
+        r#"<p><small>Published on {date}</small></p>
+    {html}
+    <p><a href="/archive">All issues</a></p>"#,
+        date = issue.published_at.format("%B %-d, %Y"),
+        html = issue.html_content,
+    );
+    Ok(landing_page(
+        StatusCode::OK,
+        &escape_html(&issue.title),
+        &content,
+    ))
+}
+
+/// The latest public issues as an Atom feed.
+#[tracing::instrument(name = "Serving the Atom feed", skip_all)]
+pub async fn atom_feed(
+    pool: web::Data<PgPool>,
+    settings: web::Data<ArchiveSettings>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ArchiveError> {
+    let base_url = base_url.0.trim_end_matches('/');
+    let issues = get_public_issues(&pool, Some(settings.feed_length)).await?;
+    // `escape_html` escapes everything XML needs escaping too.
+    let title = escape_html(&settings.title);
+    let subtitle = match settings.description.as_str() {
+        "" => String::new(),
+        description => format!("\n    <subtitle>{}</subtitle>", escape_html(description)),
+    };
+    let updated = issues
+        .first()
+        .map_or_else(Utc::now, |issue| issue.published_at)
+        .to_rfc3339();
+    let entries: String = issues
+        .iter()
+        .map(|issue| {
+            let published = issue.published_at.to_rfc3339();
+            format!(
+                r#"
+    <entry>
+        <title>{title}</title>
+        <link href="{url}"/>
+        <id>{id}</id>
+        <published>{published}</published>
+        <updated>{published}</updated>
+        <content type="html">{content}</content>
+    </entry>"#,
+                title = escape_html(&issue.title),
+                url = escape_html(&issue.url(base_url)),
+                id = issue.feed_id(),
+                content = escape_html(&issue.html_content),
+            )
+        })
+        .collect();
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+    <title>{title}</title>{subtitle}
+    <link href="{base_url}/archive"/>
+    <link rel="self" href="{base_url}/feed.xml"/>
+    <id>{base_url}/archive</id>
+    <updated>{updated}</updated>
+    <author><name>{title}</name></author>{entries}
+</feed>
+"#,
+        base_url = escape_html(base_url),
+    );
+    Ok(HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .body(body))
+}
+
+/// The latest public issues as an RSS 2.0 feed.
+#[tracing::instrument(name = "Serving the RSS feed", skip_all)]
+pub async fn rss_feed(
+    pool: web::Data<PgPool>,
+    settings: web::Data<ArchiveSettings>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ArchiveError> {
+    let base_url = base_url.0.trim_end_matches('/');
+    let issues = get_public_issues(&pool, Some(settings.feed_length)).await?;
+    let items: String = issues
+        .iter()
+        .map(|issue| {
+            format!(
+                r#"
+        <item>
+            <title>{title}</title>
+            <link>{url}</link>
+            <guid isPermaLink="false">{id}</guid>
+            <pubDate>{published}</pubDate>
+            <description>{content}</description>
+        </item>"#,
+                title = escape_html(&issue.title),
+                url = escape_html(&issue.url(base_url)),
+                id = issue.feed_id(),
+                published = issue.published_at.to_rfc2822(),
+                content = escape_html(&issue.html_content),
+            )
+        })
+        .collect();
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0">
+    <channel>
+        <title>{title}</title>
+        <link>{base_url}/archive</link>
+        <description>{description}</description>{items}
+    </channel>
+</rss>
+"#,
+        title = escape_html(&settings.title),
+        base_url = escape_html(base_url),
+        description = escape_html(&settings.description),
+    );
+    Ok(HttpResponse::Ok()
+        .content_type("application/rss+xml; charset=utf-8")
+        .body(body))
+}
+
+/// The latest public issues as a JSON Feed.
+#[tracing::instrument(name = "Serving the JSON feed", skip_all)]
+pub async fn json_feed(
+    pool: web::Data<PgPool>,
+    settings: web::Data<ArchiveSettings>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ArchiveError> {
+    let base_url = base_url.0.trim_end_matches('/');
+    let issues = get_public_issues(&pool, Some(settings.feed_length)).await?;
+    let feed = JsonFeed {
+        version: "https://jsonfeed.org/version/1.1",
+        title: &settings.title,
+        description: &settings.description,
+        home_page_url: format!("{base_url}/archive"),
+        feed_url: format!("{base_url}/feed.json"),
+        items: issues
+            .iter()
+            .map(|issue| JsonFeedItem {
+                id: issue.feed_id(),
+                url: issue.url(base_url),
+                title: &issue.title,
+                content_html: &issue.html_content,
+                content_text: &issue.text_content,
+                date_published: issue.published_at,
+            })
+            .collect(),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("application/feed+json")
+        .json(feed))
+}
+
+/// The public issues, newest first. All of them if `limit` is `None`.
+#[tracing::instrument(name = "Getting public issues", skip(pool))]
+async fn get_public_issues(
+    pool: &PgPool,
+    limit: Option<i64>,
+) -> Result<Vec<ArchivedIssue>, anyhow::Error> {
+    sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT newsletter_issue_id, slug, title, html_content, text_content, published_at
+        FROM newsletter_issues
+        WHERE public
+        ORDER BY published_at DESC
+        LIMIT $1
+        "#,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the public issues.")
+}
+
+#[tracing::instrument(name = "Getting a public issue", skip(pool))]
+async fn get_public_issue(
+    pool: &PgPool,
+    slug: &str,
+) -> Result<Option<ArchivedIssue>, anyhow::Error> {
+    sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT newsletter_issue_id, slug, title, html_content, text_content, published_at
+        FROM newsletter_issues
+        WHERE public AND slug = $1
+        "#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a public issue.")
+}
@@ -0,0 +1,179 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    authentication::Admin,
+    configuration::SharedRuntimeSettings,
+    domain::{EmailFormat, SubscriberEmail},
+    errors::DraftError,
+    issue_delivery::{render_issue, NewsletterIssue, RenderedIssue},
+    routes::Content,
+    startup::ApplicationBaseUrl,
+    subscription_tokens::{SubscriberLinks, TokenHasher},
+};
+
+/// Test copies go to a handful of colleagues, not to an audience.
+const MAX_TEST_RECIPIENTS: usize = 10;
+
+/// An issue that has not been published. Nothing about it is stored.
+#[derive(Deserialize)]
+pub struct DraftData {
+    title: String,
+    content: Content,
+}
+
+impl DraftData {
+    fn issue(&self) -> NewsletterIssue {
+        NewsletterIssue {
+            title: self.title.clone(),
+            text_content: self.content.text.clone(),
+            html_content: self.content.html.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PreviewData {
+    #[serde(flatten)]
+    draft: DraftData,
+    /// The draft is rendered as this subscriber would receive it.
+    subscriber_email: String,
+}
+
+#[derive(Deserialize)]
+pub struct TestSendData {
+    #[serde(flatten)]
+    draft: DraftData,
+    recipients: Vec<String>,
+    /// Render the test copy as this subscriber would receive it. Without
+    /// one, it has no preferences or unsubscribe link.
+    #[serde(default)]
+    subscriber_email: Option<String>,
+}
+
+/// The body of the response to a test send.
+#[derive(Serialize)]
+pub struct TestSendReport {
+    pub sent: usize,
+}
+
+/// The email a subscriber would receive if the draft was published.
+#[tracing::instrument(
+    name = "Previewing a draft issue",
+    skip(_admin, body, pool, base_url, token_hasher)
+)]
+pub async fn preview_draft(
+    _admin: Admin,
+    body: web::Json<PreviewData>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    token_hasher: web::Data<TokenHasher>,
+) -> Result<HttpResponse, DraftError> {
+    let rendered = render_draft(
+        &pool,
+        &base_url.0,
+        &token_hasher,
+        &body.draft,
+        Some(&body.subscriber_email),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(rendered))
+}
+
+/// Send a copy of the draft to `recipients`, without publishing it or
+/// recording any delivery.
+#[tracing::instrument(
+    name = "Sending a test copy of a draft issue",
+    skip(_admin, body, pool, runtime_settings, base_url, token_hasher)
+)]
+pub async fn send_test_draft(
+    _admin: Admin,
+    body: web::Json<TestSendData>,
+    pool: web::Data<PgPool>,
+    runtime_settings: web::Data<SharedRuntimeSettings>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    token_hasher: web::Data<TokenHasher>,
+) -> Result<HttpResponse, DraftError> {
+    if body.recipients.is_empty() || body.recipients.len() > MAX_TEST_RECIPIENTS {
+        return Err(DraftError::InvalidRecipients(format!(
+            "Send test copies to between 1 and {MAX_TEST_RECIPIENTS} addresses."
+        )));
+    }
+    let recipients = body
+        .recipients
+        .iter()
+        .map(|recipient| {
+            SubscriberEmail::parse(recipient.clone())
+                .map_err(|e| DraftError::InvalidRecipients(e.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let rendered = render_draft(
+        &pool,
+        &base_url.0,
+        &token_hasher,
+        &body.draft,
+        body.subscriber_email.as_deref(),
+    )
+    .await?;
+    let subject = format!("[Test] {}", rendered.subject);
+
+    let email_client = &runtime_settings.current().email_client;
+    let mut failed = 0;
+    for recipient in &recipients {
+        if let Err(error) = email_client
+            .send_email(
+                recipient,
+                &subject,
+                &rendered.html_content,
+                &rendered.text_content,
+            )
+            .await
+        {
+            failed += 1;
+            tracing::error!(
+                error.cause_chain = ?error,
+                error.message = %error,
+                "Failed to send a test copy."
+            );
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("Failed to send a test copy to {failed} recipient(s).").into());
+    }
+    Ok(HttpResponse::Ok().json(TestSendReport {
+        sent: recipients.len(),
+    }))
+}
+
+/// Render `draft` as `subscriber_email` would receive it, or as a bare HTML
+/// email without a subscriber.
+async fn render_draft(
+    pool: &PgPool,
+    base_url: &str,
+    token_hasher: &TokenHasher,
+    draft: &DraftData,
+    subscriber_email: Option<&str>,
+) -> Result<RenderedIssue, DraftError> {
+    let issue = draft.issue();
+    let Some(subscriber_email) = subscriber_email else {
+        return Ok(render_issue(&issue, None, EmailFormat::Html));
+    };
+    let subscriber_email = SubscriberEmail::parse(subscriber_email.to_string())
+        .map_err(|_| DraftError::UnknownSubscriber)?;
+    let subscriber = sqlx::query!(
+        "SELECT id, email_format FROM subscriptions WHERE lower(email) = lower($1)",
+        subscriber_email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the sample subscriber.")?
+    .ok_or(DraftError::UnknownSubscriber)?;
+    let email_format = subscriber
+        .email_format
+        .parse()
+        .map_err(anyhow::Error::msg)?;
+    let links = SubscriberLinks::new(base_url, token_hasher, subscriber.id);
+    Ok(render_issue(&issue, Some(&links), email_format))
+}
@@ -1,9 +1,24 @@
+mod admin;
+mod archive;
+mod drafts;
 mod health_check;
 mod newsletter;
+pub(crate) mod pages;
+mod preferences;
+mod resend_confirmation;
 mod subscription_confirmation;
+mod subscription_form;
 mod subscriptions;
+mod unsubscribe;
 
+pub use admin::*;
+pub use archive::*;
+pub use drafts::*;
 pub use health_check::*;
 pub use newsletter::*;
+pub use preferences::*;
+pub use resend_confirmation::*;
 pub use subscription_confirmation::*;
+pub use subscription_form::*;
 pub use subscriptions::*;
+pub use unsubscribe::*;
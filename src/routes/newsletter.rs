@@ -1,20 +1,70 @@
 use actix_web::{web, HttpResponse};
 use anyhow::Context;
-use serde::Deserialize;
-use sqlx::PgPool;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, QueryBuilder, Row};
 
-use crate::{domain::SubscriberEmail, email_client::EmailClient, errors::PublishError};
+use crate::{
+    authentication::Admin,
+    configuration::{SharedRuntimeSettings, Topics},
+    domain::SubscriberEmail,
+    errors::PublishError,
+    issue_delivery::{deliver_issue, enqueue_delivery, insert_newsletter_issue},
+    segment::Segment,
+    startup::ApplicationBaseUrl,
+    subscription_tokens::TokenHasher,
+};
 
 #[derive(Deserialize)]
 pub struct BodyData {
     title: String,
     content: Content,
+    /// Whether the issue is listed in the public archive and feeds.
+    #[serde(default)]
+    public: bool,
+    #[serde(flatten)]
+    audience: AudienceData,
+}
+
+/// Who an issue goes to, besides having confirmed and wanting every issue.
+#[derive(Deserialize, Default)]
+pub struct AudienceData {
+    /// Only subscribers interested in this topic get the issue. Everyone
+    /// does, if unset.
+    #[serde(default)]
+    topic: Option<String>,
+    /// Only subscribers in this [`Segment`] get the issue.
+    #[serde(default)]
+    segment: Option<String>,
+}
+
+struct Audience {
+    topic: Option<String>,
+    segment: Option<Segment>,
+}
+
+impl AudienceData {
+    fn parse(&self, topics: &Topics) -> Result<Audience, PublishError> {
+        if let Some(topic) = &self.topic {
+            topics.check(topic)?;
+        }
+        let segment = self.segment.as_deref().map(Segment::parse).transpose()?;
+        Ok(Audience {
+            topic: self.topic.clone(),
+            segment,
+        })
+    }
+}
+
+/// The body of the response to a dry run.
+#[derive(Serialize)]
+pub struct DryRunReport {
+    pub recipients: i64,
 }
 
 #[derive(Deserialize)]
 pub struct Content {
-    html: String,
-    text: String,
+    pub(crate) html: String,
+    pub(crate) text: String,
 }
 
 struct ConfirmedSubscriber {
@@ -24,23 +74,41 @@ struct ConfirmedSubscriber {
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     db_pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    runtime_settings: web::Data<SharedRuntimeSettings>,
+    topics: web::Data<Topics>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    token_hasher: web::Data<TokenHasher>,
 ) -> Result<HttpResponse, PublishError> {
-    let subscribers = get_confirmed_subscribers(&db_pool).await?;
+    let runtime_settings = runtime_settings.current();
+    let audience = body.audience.parse(&topics)?;
+    let subscribers = get_confirmed_subscribers(&db_pool, &audience).await?;
+
+    let mut tx = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let issue_id = insert_newsletter_issue(
+        &mut tx,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+        audience.topic.as_deref(),
+        body.audience.segment.as_deref(),
+        body.public,
+    )
+    .await
+    .context("Failed to store newsletter issue details.")?;
 
     for subscriber in subscribers {
         match subscriber {
             Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
+                enqueue_delivery(&mut tx, issue_id, &subscriber.email)
                     .await
                     .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
+                        format!(
+                            "Failed to enqueue newsletter issue for {}",
+                            subscriber.email
+                        )
                     })?;
             }
             Err(error) => {
@@ -52,28 +120,92 @@ pub async fn publish_newsletter(
             }
         }
     }
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to store a newsletter issue.")?;
+
+    let report = deliver_issue(
+        &db_pool,
+        &runtime_settings.email_client,
+        &base_url.0,
+        &token_hasher,
+        issue_id,
+    )
+    .await?;
+    if report.failed > 0 {
+        return Err(anyhow::anyhow!(
+            "Failed to send newsletter issue {} to {} subscriber(s).",
+            issue_id,
+            report.failed
+        )
+        .into());
+    }
     Ok(HttpResponse::Ok().finish())
 }
 
-#[tracing::instrument(name = "Getting confirmed subscribers", skip(db_pool))]
+/// How many subscribers an issue would go to, without publishing anything.
+/// Operators only: segments can probe what is known about subscribers.
+pub async fn newsletter_dry_run(
+    _admin: Admin,
+    body: web::Json<AudienceData>,
+    db_pool: web::Data<PgPool>,
+    topics: web::Data<Topics>,
+) -> Result<HttpResponse, PublishError> {
+    let audience = body.parse(&topics)?;
+    let recipients = audience_query("SELECT COUNT(*)", &audience)
+        .build()
+        .fetch_one(db_pool.get_ref())
+        .await
+        .context("Failed to count the recipients.")?
+        .try_get(0)
+        .context("Failed to read the recipient count.")?;
+    Ok(HttpResponse::Ok().json(DryRunReport { recipients }))
+}
+
+/// `select` over the confirmed subscribers in `audience` who get every issue
+/// as it is published.
+fn audience_query<'a>(select: &str, audience: &Audience) -> QueryBuilder<'a, Postgres> {
+    let mut query = QueryBuilder::new(select);
+    query.push(
+        " FROM subscriptions s \
+        WHERE s.status = 'confirmed' AND s.frequency = 'every_issue'",
+    );
+    if let Some(topic) = &audience.topic {
+        // Subscribers who picked no topic at all get every topic.
+        query
+            .push(
+                " AND (NOT EXISTS (SELECT 1 FROM subscriber_topics t WHERE t.subscriber_id = s.id) \
+                OR EXISTS (SELECT 1 FROM subscriber_topics t \
+                WHERE t.subscriber_id = s.id AND t.topic = ",
+            )
+            .push_bind(topic.clone())
+            .push("))");
+    }
+    if let Some(segment) = &audience.segment {
+        query.push(" AND ");
+        segment.push_sql(&mut query);
+    }
+    query
+}
+
+#[tracing::instrument(name = "Getting confirmed subscribers", skip_all)]
 async fn get_confirmed_subscribers(
     db_pool: &PgPool,
+    audience: &Audience,
 ) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers = sqlx::query!(
-        r#"
-        SELECT email
-        FROM subscriptions
-        WHERE status = 'confirmed'
-        "#
-    )
-    .fetch_all(db_pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow::anyhow!(error)),
-    })
-    .collect();
+    let confirmed_subscribers = audience_query("SELECT s.email", audience)
+        .build()
+        .fetch_all(db_pool)
+        .await?
+        .into_iter()
+        .map(|row| {
+            let email: String = row.try_get("email")?;
+            match SubscriberEmail::parse(email) {
+                Ok(email) => Ok(ConfirmedSubscriber { email }),
+                Err(error) => Err(anyhow::anyhow!(error)),
+            }
+        })
+        .collect();
 
     Ok(confirmed_subscribers)
 }
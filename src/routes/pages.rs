@@ -0,0 +1,59 @@
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    HttpResponse,
+};
+
+/// A page shown to subscribers after following a link or submitting a form.
+/// `content` is inserted as is: escape anything that comes from users.
+pub(crate) fn landing_page(status: StatusCode, title: &str, content: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <title>{title}</title>
+    <style>
+        body {{ font-family: system-ui, sans-serif; max-width: 36rem; margin: 4rem auto; padding: 0 1rem; color: #1f2933; }}
+        h1 {{ font-size: 1.5rem; }}
+        button {{ padding: 0.5rem 1rem; }}
+    </style>
+</head>
+<body>
+    <h1>{title}</h1>
+    {content}
+</body>
+</html>"#
+        ))
+}
+
+/// Escape text for use in HTML content and quoted attributes.
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_html;
+
+    #[test]
+    fn html_is_escaped() {
+        assert_eq!(
+            escape_html(r#""><script>alert('hi')</script>@example.com"#),
+            "&quot;&gt;&lt;script&gt;alert(&#39;hi&#39;)&lt;/script&gt;@example.com"
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::{BTreeSet, HashMap};
+
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{Topic, Topics},
+    domain::{EmailFormat, Frequency, SubscriberName, ValidationErrors},
+    errors::PreferencesError,
+    routes::{
+        pages::{escape_html, landing_page},
+        subscriptions::{wants_json, FormOrJson},
+    },
+    subscription_tokens::TokenHasher,
+};
+
+/// Prefix of the topic checkboxes in the HTML form: forms cannot submit a
+/// list under a single name.
+const TOPIC_CHECKBOX_PREFIX: &str = "topic.";
+
+#[derive(Deserialize)]
+pub struct PreferencesParameters {
+    pub(super) token: String,
+}
+
+/// What a subscriber can change from the preferences page.
+#[derive(Serialize, Debug)]
+pub struct Preferences {
+    pub email: String,
+    pub name: String,
+    pub email_format: EmailFormat,
+    pub frequency: Frequency,
+    /// The topics they want to hear about, every topic if empty.
+    pub topics: Vec<String>,
+}
+
+/// The body of the response for clients that prefer JSON.
+#[derive(Serialize)]
+pub struct PreferencesResponse<'a> {
+    #[serde(flatten)]
+    pub preferences: &'a Preferences,
+    pub available_topics: Vec<&'a Topic>,
+}
+
+#[derive(Deserialize)]
+pub struct PreferencesData {
+    name: String,
+    email_format: EmailFormat,
+    frequency: Frequency,
+    #[serde(default)]
+    topics: Vec<String>,
+    /// The HTML form sends each picked topic as a `topic.<id>` checkbox.
+    #[serde(flatten)]
+    fields: HashMap<String, String>,
+}
+
+struct NewPreferences {
+    name: SubscriberName,
+    email_format: EmailFormat,
+    frequency: Frequency,
+    topics: BTreeSet<String>,
+}
+
+impl PreferencesData {
+    /// Fails with the problems of every field, not just the first one.
+    fn parse(self, topics: &Topics) -> Result<NewPreferences, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        let name = SubscriberName::parse(self.name)
+            .map_err(|e| e.into_iter().for_each(|e| errors.push(e)))
+            .ok();
+        let picked: BTreeSet<_> = self
+            .topics
+            .into_iter()
+            .chain(self.fields.into_keys().filter_map(|field| {
+                field
+                    .strip_prefix(TOPIC_CHECKBOX_PREFIX)
+                    .map(ToString::to_string)
+            }))
+            .collect();
+        for topic in &picked {
+            if let Err(e) = topics.check(topic) {
+                errors.push(e);
+            }
+        }
+
+        match name {
+            Some(name) if errors.is_empty() => Ok(NewPreferences {
+                name,
+                email_format: self.email_format,
+                frequency: self.frequency,
+                topics: picked,
+            }),
+            _ => Err(errors),
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Showing subscriber preferences",
+    skip(request, parameters, pool, token_hasher, topics)
+)]
+pub async fn preferences_form(
+    request: HttpRequest,
+    parameters: web::Query<PreferencesParameters>,
+    pool: web::Data<PgPool>,
+    token_hasher: web::Data<TokenHasher>,
+    topics: web::Data<Topics>,
+) -> Result<HttpResponse, PreferencesError> {
+    let Some(subscriber_id) = token_hasher.verify_preferences_token(&parameters.token) else {
+        return invalid_link(&request);
+    };
+    let Some(preferences) = get_preferences(&pool, subscriber_id).await? else {
+        return invalid_link(&request);
+    };
+    Ok(preferences_response(
+        &request,
+        &parameters.token,
+        &preferences,
+        &topics,
+        None,
+    ))
+}
+
+#[tracing::instrument(
+    name = "Updating subscriber preferences",
+    skip(request, parameters, body, pool, token_hasher, topics)
+)]
+pub async fn update_preferences(
+    request: HttpRequest,
+    parameters: web::Query<PreferencesParameters>,
+    body: FormOrJson<PreferencesData>,
+    pool: web::Data<PgPool>,
+    token_hasher: web::Data<TokenHasher>,
+    topics: web::Data<Topics>,
+) -> Result<HttpResponse, PreferencesError> {
+    let Some(subscriber_id) = token_hasher.verify_preferences_token(&parameters.token) else {
+        return invalid_link(&request);
+    };
+    let new_preferences = body.0.parse(&topics)?;
+    if !save_preferences(&pool, subscriber_id, &new_preferences).await? {
+        return invalid_link(&request);
+    }
+    let preferences = get_preferences(&pool, subscriber_id)
+        .await?
+        .context("The subscriber disappeared while saving their preferences.")?;
+    Ok(preferences_response(
+        &request,
+        &parameters.token,
+        &preferences,
+        &topics,
+        Some("Your preferences were saved."),
+    ))
+}
+
+#[tracing::instrument(name = "Getting subscriber preferences", skip(pool))]
+async fn get_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Preferences>, anyhow::Error> {
+    let Some(row) = sqlx::query!(
+        r#"
+        SELECT
+            display_email,
+            name,
+            email_format,
+            frequency,
+            ARRAY(
+                SELECT topic FROM subscriber_topics
+                WHERE subscriber_id = s.id
+                ORDER BY topic
+            ) AS "topics!"
+        FROM subscriptions s
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve subscriber preferences.")?
+    else {
+        return Ok(None);
+    };
+    Ok(Some(Preferences {
+        email: row.display_email,
+        name: row.name,
+        email_format: row.email_format.parse().map_err(anyhow::Error::msg)?,
+        frequency: row.frequency.parse().map_err(anyhow::Error::msg)?,
+        topics: row.topics,
+    }))
+}
+
+/// Replace the preferences of a subscriber. `false` if they no longer exist.
+#[tracing::instrument(name = "Saving subscriber preferences", skip(pool, preferences))]
+async fn save_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    preferences: &NewPreferences,
+) -> Result<bool, anyhow::Error> {
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let updated = tx
+        .execute(sqlx::query!(
+            r#"
+            UPDATE subscriptions
+            SET
+                name = $2,
+                email_format = $3,
+                frequency = $4,
+                -- Digests only cover what was published after switching to them.
+                last_digest_at = CASE
+                    WHEN frequency <> $4 AND $4 = 'weekly_digest' THEN now()
+                    ELSE last_digest_at
+                END
+            WHERE id = $1
+            "#,
+            subscriber_id,
+            preferences.name.as_ref(),
+            preferences.email_format.as_str(),
+            preferences.frequency.as_str()
+        ))
+        .await
+        .context("Failed to update the subscriber.")?;
+    if updated.rows_affected() == 0 {
+        return Ok(false);
+    }
+    tx.execute(sqlx::query!(
+        "DELETE FROM subscriber_topics WHERE subscriber_id = $1",
+        subscriber_id
+    ))
+    .await
+    .context("Failed to delete the previous topics.")?;
+    let topics: Vec<_> = preferences.topics.iter().cloned().collect();
+    tx.execute(sqlx::query!(
+        r#"
+        INSERT INTO subscriber_topics (subscriber_id, topic)
+        SELECT $1, topic FROM UNNEST($2::text[]) AS topic
+        "#,
+        subscriber_id,
+        &topics
+    ))
+    .await
+    .context("Failed to store the topics.")?;
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to save preferences.")?;
+    Ok(true)
+}
+
+pub(super) fn invalid_link(request: &HttpRequest) -> Result<HttpResponse, PreferencesError> {
+    if wants_json(request) {
+        return Err(PreferencesError::InvalidToken);
+    }
+    Ok(landing_page(
+        StatusCode::NOT_FOUND,
+        "This link is not valid",
+        "<p>Use the link at the bottom of our latest email to manage your preferences.</p>",
+    ))
+}
+
+fn preferences_response(
+    request: &HttpRequest,
+    token: &str,
+    preferences: &Preferences,
+    topics: &Topics,
+    notice: Option<&str>,
+) -> HttpResponse {
+    if wants_json(request) {
+        return HttpResponse::Ok().json(PreferencesResponse {
+            preferences,
+            available_topics: topics.iter().collect(),
+        });
+    }
+
+    let notice = notice
+        .map(|notice| format!(r#"<p role="status"><strong>{notice}</strong></p>"#))
+        .unwrap_or_default();
+    let checked = |yes: bool| if yes { " checked" } else { "" };
+    let topic_fieldset = if topics.iter().next().is_none() {
+        String::new()
+    } else {
+        let checkboxes: String = topics
+            .iter()
+            .map(|topic| {
+                format!(
+                    r#"
+            <label><input type="checkbox" name="{TOPIC_CHECKBOX_PREFIX}{id}"{checked}> {name}</label><br>"#,
+                    id = escape_html(&topic.id),
+                    name = escape_html(&topic.name),
+                    checked = checked(preferences.topics.contains(&topic.id)),
+                )
+            })
+            .collect();
+        format!(
+            r#"
+        <fieldset>
+            <legend>Topics</legend>
+            <p>Leave them all unchecked to hear about everything.</p>{checkboxes}
+        </fieldset>"#
+        )
+    };
+    let content = format!(
+        r#"{notice}<p>Preferences for {email}.</p>
+    <form action="/preferences?token={token}" method="post">
+        <p><label>Name <input type="text" name="name" value="{name}" required></label></p>
+        <fieldset>
+            <legend>Format</legend>
+            <label><input type="radio" name="email_format" value="html"{html}> HTML</label><br>
+            <label><input type="radio" name="email_format" value="plain_text"{plain_text}> Plain text only</label>
+        </fieldset>
+        <fieldset>
+            <legend>Frequency</legend>
+            <label><input type="radio" name="frequency" value="every_issue"{every_issue}> Every issue</label><br>
+            <label><input type="radio" name="frequency" value="weekly_digest"{weekly_digest}> A weekly digest</label>
+        </fieldset>{topic_fieldset}
+        <p><button type="submit">Save</button></p>
+    </form>
+    <p><a href="/unsubscribe?token={token}">Unsubscribe</a> from every email instead.</p>"#,
+        email = escape_html(&preferences.email),
+        token = escape_html(token),
+        name = escape_html(&preferences.name),
+        html = checked(preferences.email_format == EmailFormat::Html),
+        plain_text = checked(preferences.email_format == EmailFormat::PlainText),
+        every_issue = checked(preferences.frequency == Frequency::EveryIssue),
+        weekly_digest = checked(preferences.frequency == Frequency::WeeklyDigest),
+    );
+    landing_page(StatusCode::OK, "Your preferences", &content)
+}
@@ -0,0 +1,145 @@
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    configuration::SharedRuntimeSettings,
+    domain::{SubscriberEmail, ValidationErrors},
+    errors::SubscribeError,
+    rate_limit::{RateLimitKey, RateLimiter},
+    routes::{
+        enqueue_confirmation_email,
+        pages::landing_page,
+        store_token,
+        subscriptions::{wants_json, FormOrJson},
+    },
+    startup::ApplicationBaseUrl,
+    subscription_tokens::{generate_subscription_token, TokenHasher},
+};
+
+#[derive(Deserialize)]
+pub struct ResendData {
+    pub email: String,
+}
+
+/// The body of the response for clients that prefer JSON.
+#[derive(Serialize)]
+pub struct ResendStatus {
+    pub status: &'static str,
+}
+
+/// Send a new confirmation link to a pending subscriber, replacing the old
+/// one.
+///
+/// The response is the same whether or not the address has a pending
+/// subscription, so it cannot be used to find out who is subscribed.
+#[tracing::instrument(
+    name = "Resending a confirmation email",
+    skip(request, body, pool, runtime_settings, rate_limiter, base_url, token_hasher),
+    fields(subscriber_email = %body.0.email)
+)]
+pub async fn resend_confirmation(
+    request: HttpRequest,
+    body: FormOrJson<ResendData>,
+    pool: web::Data<PgPool>,
+    runtime_settings: web::Data<SharedRuntimeSettings>,
+    rate_limiter: web::Data<RateLimiter>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    token_hasher: web::Data<TokenHasher>,
+) -> Result<HttpResponse, SubscribeError> {
+    let runtime_settings = runtime_settings.current();
+    let email = SubscriberEmail::parse(body.0.email).map_err(ValidationErrors::from)?;
+    rate_limiter
+        .check(
+            &runtime_settings.rate_limit,
+            "resend_confirmation",
+            RateLimitKey::Email(email.as_ref()),
+        )
+        .await?;
+
+    if let Err(e) = resend(&pool, &token_hasher, &email, &base_url.0).await {
+        // Failing here would tell the client the address has a pending
+        // subscription.
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to resend a confirmation email."
+        );
+    }
+    Ok(resend_requested(&request))
+}
+
+/// Rotate the confirmation token of the pending subscription for `email`,
+/// if there is one, and send the new link.
+pub(super) async fn resend(
+    pool: &PgPool,
+    token_hasher: &TokenHasher,
+    email: &SubscriberEmail,
+    base_url: &str,
+) -> Result<(), anyhow::Error> {
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let Some(subscriber_id) = get_pending_subscriber_id(&mut tx, email)
+        .await
+        .context("Failed to look up the pending subscriber.")?
+    else {
+        tracing::info!("No pending subscription for this address, not sending anything.");
+        return Ok(());
+    };
+    let subscription_token = generate_subscription_token();
+    tx.execute(sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    ))
+    .await
+    .context("Failed to delete the previous confirmation tokens.")?;
+    store_token(&mut tx, token_hasher, subscriber_id, &subscription_token)
+        .await
+        .context("Failed to store the new confirmation token.")?;
+    enqueue_confirmation_email(&mut tx, email, base_url, &subscription_token)
+        .await
+        .context("Failed to add the confirmation email to the outbox.")?;
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to rotate a confirmation token.")?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Getting a pending subscriber by email",
+    skip(transaction, email)
+)]
+async fn get_pending_subscriber_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE lower(email) = lower($1) AND status = 'pending_confirmation'
+        FOR UPDATE
+        "#,
+        email.as_ref()
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|r| r.id))
+}
+
+fn resend_requested(request: &HttpRequest) -> HttpResponse {
+    if wants_json(request) {
+        HttpResponse::Ok().json(ResendStatus {
+            status: "resend_requested",
+        })
+    } else {
+        landing_page(
+            StatusCode::OK,
+            "Check your inbox",
+            "<p>If this address has a subscription waiting for confirmation, we sent it a new link.</p>",
+        )
+    }
+}
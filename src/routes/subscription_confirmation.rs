@@ -1,41 +1,154 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{
+    http::{header::LOCATION, StatusCode},
+    web, HttpResponse,
+};
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 use uuid::Uuid;
 
-use crate::errors::{RetrieveSubscriberIdError, SubscriptionConfirmationError};
+use crate::{
+    configuration::ConfirmationSettings,
+    errors::{RetrieveSubscriberIdError, SubscriptionConfirmationError},
+    routes::pages::{escape_html, landing_page},
+    subscription_tokens::TokenHasher,
+};
 
 #[derive(Deserialize)]
 pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirming a pending subscriber", skip(db_pool, parameters))]
+/// What following a confirmation link did. Each outcome has its own landing
+/// page.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConfirmationOutcome {
+    Confirmed,
+    AlreadyConfirmed,
+    /// The link is too old. The page offers to send a new one to `email`.
+    Expired {
+        email: String,
+    },
+    Invalid,
+}
+
+impl ConfirmationOutcome {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Confirmed | Self::AlreadyConfirmed => StatusCode::OK,
+            Self::Expired { .. } => StatusCode::GONE,
+            Self::Invalid => StatusCode::NOT_FOUND,
+        }
+    }
+
+    fn redirect<'a>(&self, settings: &'a ConfirmationSettings) -> Option<&'a str> {
+        let redirects = &settings.redirects;
+        match self {
+            Self::Confirmed => redirects.confirmed.as_deref(),
+            Self::AlreadyConfirmed => redirects.already_confirmed.as_deref(),
+            Self::Expired { .. } => redirects.expired.as_deref(),
+            Self::Invalid => redirects.invalid.as_deref(),
+        }
+    }
+
+    /// Our landing page, or a redirect to the one configured for this
+    /// outcome.
+    fn response(&self, settings: &ConfirmationSettings) -> HttpResponse {
+        if let Some(url) = self.redirect(settings) {
+            return HttpResponse::SeeOther()
+                .insert_header((LOCATION, url))
+                .finish();
+        }
+        let (title, content) = match self {
+            Self::Confirmed => (
+                "Subscription confirmed",
+                "<p>Thanks for confirming your subscription! Our next issue will be in your inbox.</p>"
+                    .to_string(),
+            ),
+            Self::AlreadyConfirmed => (
+                "Already confirmed",
+                "<p>Your subscription was already confirmed, there is nothing else to do.</p>"
+                    .to_string(),
+            ),
+            Self::Expired { email } => (
+                "This link has expired",
+                format!(
+                    r#"<p>Confirmation links are only valid for a few days. We can send a new one to {email}.</p>
+    <form action="/subscriptions/resend-confirmation" method="post">
+        <input type="hidden" name="email" value="{email}">
+        <button type="submit">Send a new link</button>
+    </form>"#,
+                    email = escape_html(email)
+                ),
+            ),
+            Self::Invalid => (
+                "This link is not valid",
+                r#"<p>Check that you opened the whole link from the email, or <a href="/subscriptions">subscribe again</a>.</p>"#
+                    .to_string(),
+            ),
+        };
+
+        landing_page(self.status_code(), title, &content)
+    }
+}
+
+/// The token a confirmation link carries, with what we need to know about its
+/// subscriber.
+struct TokenRecord {
+    token_hash: String,
+    subscriber_id: Uuid,
+    status: String,
+    display_email: String,
+    created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "Confirming a pending subscriber",
+    skip(db_pool, parameters, settings, token_hasher)
+)]
 pub async fn confirm(
     db_pool: web::Data<PgPool>,
     parameters: web::Query<Parameters>,
+    settings: web::Data<ConfirmationSettings>,
+    token_hasher: web::Data<TokenHasher>,
 ) -> Result<HttpResponse, SubscriptionConfirmationError> {
-    let id = get_subscriber_id_from_token(&db_pool, &parameters.subscription_token)
+    let record = get_token_record(&db_pool, &token_hasher, &parameters.subscription_token)
         .await
         .context("Failed to retrieve subscriber id from database.")?;
 
-    match id {
-        None => Ok(HttpResponse::Unauthorized().finish()),
-        Some(subscriber_id) => {
-            confirm_subscriber(&db_pool, subscriber_id)
+    let outcome = match record {
+        None => ConfirmationOutcome::Invalid,
+        Some(record) if record.status == "confirmed" => ConfirmationOutcome::AlreadyConfirmed,
+        Some(record)
+            if (Utc::now() - record.created_at)
+                .to_std()
+                .unwrap_or_default()
+                > settings.token_ttl() =>
+        {
+            ConfirmationOutcome::Expired {
+                email: record.display_email,
+            }
+        }
+        Some(record) => {
+            confirm_subscriber(db_pool.get_ref(), record.subscriber_id)
                 .await
                 .context("Failed to confirm subscription in database.")?;
-            Ok(HttpResponse::Ok().finish())
+            ConfirmationOutcome::Confirmed
         }
-    }
+    };
+    tracing::info!(?outcome, "Followed a confirmation link.");
+    Ok(outcome.response(&settings))
 }
 
 #[tracing::instrument(
     name = "Marking subscriber as confirmed in database",
-    skip(db_pool, subscriber_id)
+    skip(executor, subscriber_id)
 )]
-async fn confirm_subscriber(db_pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+pub async fn confirm_subscriber(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"
         UPDATE subscriptions
@@ -44,30 +157,34 @@ async fn confirm_subscriber(db_pool: &PgPool, subscriber_id: Uuid) -> Result<(),
         "#,
         subscriber_id
     )
-    .execute(db_pool)
+    .execute(executor)
     .await?;
 
     Ok(())
 }
 
 #[tracing::instrument(
-    name = "Getting subscriber ID from token",
-    skip(db_pool, subscription_token)
+    name = "Getting subscriber from token",
+    skip(db_pool, token_hasher, subscription_token)
 )]
-async fn get_subscriber_id_from_token(
+async fn get_token_record(
     db_pool: &PgPool,
+    token_hasher: &TokenHasher,
     subscription_token: &str,
-) -> Result<Option<Uuid>, RetrieveSubscriberIdError> {
-    let result = sqlx::query!(
+) -> Result<Option<TokenRecord>, RetrieveSubscriberIdError> {
+    let record = sqlx::query_as!(
+        TokenRecord,
         r#"
-        SELECT subscriber_id FROM subscription_tokens
-        WHERE subscription_token = $1
+        SELECT t.token_hash, t.subscriber_id, t.created_at, s.status, s.display_email
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.token_hash = $1
         "#,
-        subscription_token
+        token_hasher.hash(subscription_token)
     )
     .fetch_optional(db_pool)
     .await
     .map_err(RetrieveSubscriberIdError)?;
 
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(record.filter(|record| token_hasher.verify(subscription_token, &record.token_hash)))
 }
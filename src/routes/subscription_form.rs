@@ -0,0 +1,95 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::{bot_protection::BotProtection, configuration::CaptchaProvider};
+
+/// The subscription form. With bot protection enabled it carries a hidden
+/// honeypot field, a signed timestamp and the CAPTCHA widget, if any.
+pub async fn subscription_form(bot_protection: Option<web::Data<BotProtection>>) -> HttpResponse {
+    let mut bot_fields = String::new();
+    if let Some(bot_protection) = bot_protection {
+        bot_fields.push_str(&format!(
+            r#"<div style="position: absolute; left: -10000px;" aria-hidden="true">
+        <label>Leave this field empty <input type="text" name="website" tabindex="-1" autocomplete="off"></label>
+    </div>
+    <input type="hidden" name="form_token" value="{}">"#,
+            bot_protection.issue_form_token(Utc::now())
+        ));
+        if let Some(captcha) = &bot_protection.captcha {
+            let (script, class) = match captcha.provider {
+                CaptchaProvider::Hcaptcha => ("https://js.hcaptcha.com/1/api.js", "h-captcha"),
+                CaptchaProvider::Turnstile => (
+                    "https://challenges.cloudflare.com/turnstile/v0/api.js",
+                    "cf-turnstile",
+                ),
+            };
+            bot_fields.push_str(&format!(
+                r#"
+    <script src="{script}" async defer></script>
+    <div class="{class}" data-sitekey="{}"></div>"#,
+                captcha.site_key
+            ));
+        }
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscribe</title>
+</head>
+<body>
+<form action="/subscriptions" method="post">
+    <label>Name <input type="text" name="name" required></label>
+    <label>Email <input type="email" name="email" required></label>
+    {bot_fields}
+    <button type="submit">Subscribe</button>
+</form>
+</body>
+</html>"#
+        ))
+}
+
+/// What clients that do not use our form, e.g. single-page apps posting
+/// JSON, need to pass bot protection.
+#[derive(Serialize)]
+pub struct FormTokenResponse<'a> {
+    /// Send it back as `form_token`, along with an empty `website`. `None`
+    /// if bot protection is disabled.
+    pub form_token: Option<String>,
+    /// The widget to solve, whose response goes in `captcha_response`.
+    pub captcha: Option<CaptchaWidget<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct CaptchaWidget<'a> {
+    pub provider: CaptchaProvider,
+    pub site_key: &'a str,
+}
+
+/// The bot protection fields of the subscription form, for API clients.
+/// Submissions without a fresh token are taken for bots.
+pub async fn subscription_form_token(
+    bot_protection: Option<web::Data<BotProtection>>,
+) -> HttpResponse {
+    let Some(bot_protection) = bot_protection else {
+        return HttpResponse::Ok().json(FormTokenResponse {
+            form_token: None,
+            captcha: None,
+        });
+    };
+    HttpResponse::Ok().json(FormTokenResponse {
+        form_token: Some(bot_protection.issue_form_token(Utc::now())),
+        captcha: bot_protection
+            .captcha
+            .as_ref()
+            .map(|captcha| CaptchaWidget {
+                provider: captcha.provider,
+                site_key: &captcha.site_key,
+            }),
+    })
+}
@@ -1,74 +1,231 @@
-use actix_web::{web, HttpResponse};
+use std::{future::Future, pin::Pin};
+
+use actix_web::{
+    dev::Payload,
+    http::header::{Accept, Header},
+    web, FromRequest, HttpMessage, HttpRequest, HttpResponse,
+};
 use anyhow::Context;
 use chrono::Utc;
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
+    bot_protection::{BotCheckFields, BotProtection, Verdict},
+    configuration::SharedRuntimeSettings,
+    deliverability::DeliverabilityCheck,
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName, ValidationErrors},
+    email_outbox::enqueue_email,
     errors::{StoreTokenError, SubscribeError},
+    rate_limit::{client_ip, RateLimitKey, RateLimiter},
+    routes::resend_confirmation::resend,
     startup::ApplicationBaseUrl,
+    subscription_tokens::{generate_subscription_token, TokenHasher},
 };
 
+/// Longer signup sources are truncated.
+const MAX_SOURCE_LENGTH: usize = 64;
+
 #[derive(Deserialize)]
 pub struct FormData {
     pub name: String,
     pub email: String,
+    /// Where the form is embedded, usually set by a hidden input.
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Embedded in our form. Other clients get them from
+    /// `GET /subscriptions/form-token`.
+    #[serde(flatten)]
+    pub bot_check: BotCheckFields,
+}
+
+/// A request body, sent by an HTML form or as JSON depending on its
+/// `Content-Type`.
+pub struct FormOrJson<T>(pub T);
+
+impl<T: DeserializeOwned + 'static> FromRequest for FormOrJson<T> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(request: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let content_type = request.content_type();
+        if content_type == "application/json" || content_type.ends_with("+json") {
+            let json = web::Json::<T>::from_request(request, payload);
+            Box::pin(async move { Ok(Self(json.await?.into_inner())) })
+        } else {
+            let form = web::Form::<T>::from_request(request, payload);
+            Box::pin(async move { Ok(Self(form.await?.into_inner())) })
+        }
+    }
+}
+
+/// The body of the response for clients that prefer JSON.
+#[derive(Serialize)]
+pub struct SubscriptionStatus {
+    pub status: &'static str,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = ValidationErrors;
 
+    /// Fails with the problems of every field, not just the first one.
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(Self { name, email })
+        match (
+            SubscriberName::parse(value.name),
+            SubscriberEmail::parse(value.email),
+        ) {
+            (Ok(name), Ok(email)) => Ok(Self {
+                name,
+                email,
+                source: signup_source(value.source),
+            }),
+            (name, email) => {
+                let mut errors = ValidationErrors::default();
+                for error in name.err().into_iter().flatten() {
+                    errors.push(error);
+                }
+                if let Err(error) = email {
+                    errors.push(error);
+                }
+                Err(errors)
+            }
+        }
     }
 }
 
+/// The source is set by whoever embeds the form, not typed by subscribers:
+/// cut it short rather than turn them away over it.
+fn signup_source(source: Option<String>) -> Option<String> {
+    source
+        .map(|source| {
+            source
+                .trim()
+                .chars()
+                .take(MAX_SOURCE_LENGTH)
+                .collect::<String>()
+        })
+        .filter(|source| !source.is_empty())
+}
+
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(
+        request,
+        body,
+        pool,
+        runtime_settings,
+        rate_limiter,
+        bot_protection,
+        deliverability,
+        base_url,
+        token_hasher
+    ),
     fields (
-        subscriber_name = %form.name,
-        subscriber_email = %form.email
+        subscriber_name = %body.0.name,
+        subscriber_email = %body.0.email
     )
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    request: HttpRequest,
+    body: FormOrJson<FormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    runtime_settings: web::Data<SharedRuntimeSettings>,
+    rate_limiter: web::Data<RateLimiter>,
+    bot_protection: Option<web::Data<BotProtection>>,
+    deliverability: Option<web::Data<DeliverabilityCheck>>,
     base_url: web::Data<ApplicationBaseUrl>,
+    token_hasher: web::Data<TokenHasher>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let runtime_settings = runtime_settings.current();
+    if let Some(bot_protection) = bot_protection {
+        let client_ip = client_ip(&request, &runtime_settings.rate_limit.trusted_proxies);
+        let verdict = bot_protection
+            .check(&body.0.bot_check, client_ip, Utc::now())
+            .await?;
+        if let Verdict::Bot(reason) = verdict {
+            // Answer as if it worked, so bots learn nothing from the response.
+            tracing::warn!(reason, "Ignoring a subscription from a suspected bot.");
+            return Ok(subscribed(&request));
+        }
+    }
+    let new_subscriber: NewSubscriber = body.0.try_into()?;
+    runtime_settings
+        .email_policy
+        .check(&new_subscriber.email)
+        .map_err(ValidationErrors::from)?;
+    rate_limiter
+        .check(
+            &runtime_settings.rate_limit,
+            "subscribe",
+            RateLimitKey::Email(new_subscriber.email.as_ref()),
+        )
+        .await?;
+    if let Some(deliverability) = deliverability {
+        deliverability
+            .check(&new_subscriber.email)
+            .await
+            .map_err(ValidationErrors::from)?;
+    }
     let mut tx = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool.")?;
     let subscription_token = generate_subscription_token();
-    let subscriber_id = insert_subscriber(&mut tx, &new_subscriber)
+    let Some(subscriber_id) = insert_subscriber(&mut tx, &new_subscriber)
         .await
-        .context("Failed to insert new subscriber into the database.")?;
-    store_token(&mut tx, subscriber_id, &subscription_token)
+        .context("Failed to insert new subscriber into the database.")?
+    else {
+        // The address is already subscribed. Answer as if it was new, so the
+        // response cannot be used to find out who is subscribed.
+        drop(tx);
+        if let Err(e) = resend(&pool, &token_hasher, &new_subscriber.email, &base_url.0).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to resend a confirmation email."
+            );
+        }
+        return Ok(subscribed(&request));
+    };
+    store_token(&mut tx, &token_hasher, subscriber_id, &subscription_token)
         .await
         .context("Failed to store confirmation token for a new subscriber.")?;
-    tx.commit()
-        .await
-        .context("Failed to commit SQL transaction to store a new subscriber.")?;
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
+    enqueue_confirmation_email(
+        &mut tx,
+        &new_subscriber.email,
         &base_url.0,
         &subscription_token,
     )
     .await
-    .context("Failed to send confirmation email.")?;
-    Ok(HttpResponse::Ok().finish())
+    .context("Failed to add the confirmation email to the outbox.")?;
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new subscriber.")?;
+    Ok(subscribed(&request))
+}
+
+/// An empty 200 for HTML forms, the subscription status for clients that
+/// `Accept` JSON.
+fn subscribed(request: &HttpRequest) -> HttpResponse {
+    if wants_json(request) {
+        HttpResponse::Ok().json(SubscriptionStatus {
+            status: "pending_confirmation",
+        })
+    } else {
+        HttpResponse::Ok().finish()
+    }
 }
 
+/// Whether the client prefers a JSON response to an HTML one.
+pub(crate) fn wants_json(request: &HttpRequest) -> bool {
+    Accept::parse(request)
+        .is_ok_and(|accept| accept.preference().essence_str() == "application/json")
+}
+
+/// Returns `None`, and inserts nothing, if the address is already
+/// subscribed. An address that unsubscribed starts over as pending.
 #[tracing::instrument(
     name = "Saving new subscriber in the database",
     skip(new_subscriber, transaction)
@@ -76,33 +233,45 @@ pub async fn subscribe(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
-    let query = sqlx::query!(
+    let row = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, name, email, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions (
+            id, name, email, display_email, subscribed_at, status, signup_source
+        )
+        VALUES ($1, $2, $3, $4, $5, 'pending_confirmation', $6)
+        ON CONFLICT ((lower(email))) DO UPDATE
+        SET
+            name = EXCLUDED.name,
+            display_email = EXCLUDED.display_email,
+            subscribed_at = EXCLUDED.subscribed_at,
+            status = EXCLUDED.status,
+            signup_source = EXCLUDED.signup_source
+        WHERE subscriptions.status = 'unsubscribed'
+        RETURNING id
         "#,
         subscriber_id,
         new_subscriber.name.as_ref(),
         new_subscriber.email.as_ref(),
-        Utc::now()
-    );
-
-    transaction.execute(query).await?;
-    Ok(subscriber_id)
+        new_subscriber.email.original(),
+        Utc::now(),
+        new_subscriber.source
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|r| r.id))
 }
 
-#[tracing::instrument(
-    name = "Sending confirmation email",
-    skip(email_client, new_subscriber)
-)]
-async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+/// Add the confirmation email to the outbox, to be sent once `transaction`
+/// commits.
+#[tracing::instrument(name = "Enqueuing confirmation email", skip(transaction, email))]
+pub async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), sqlx::Error> {
     let confirmation_link = &format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
@@ -117,26 +286,27 @@ async fn send_confirmation_email(
         confirmation_link
     );
 
-    email_client
-        .send_email(&new_subscriber.email, "Welcome", html_body, plain_text_body)
-        .await
+    enqueue_email(transaction, email, "Welcome", html_body, plain_text_body).await?;
+    Ok(())
 }
 
+/// Store the hash of `subscription_token`, never the token itself.
 #[tracing::instrument(
     name = "Saving subscription token to database",
-    skip(transaction, subscriber_id, subscription_token)
+    skip(transaction, token_hasher, subscriber_id, subscription_token)
 )]
 pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
+    token_hasher: &TokenHasher,
     subscriber_id: Uuid,
     subscription_token: &str,
 ) -> Result<(), StoreTokenError> {
     let query = sqlx::query!(
         r#"
-        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+        INSERT INTO subscription_tokens (token_hash, subscriber_id)
         VALUES ($1, $2)
         "#,
-        subscription_token,
+        token_hasher.hash(subscription_token),
         subscriber_id
     );
 
@@ -144,11 +314,3 @@ pub async fn store_token(
 
     Ok(())
 }
-
-fn generate_subscription_token() -> String {
-    let mut rng = thread_rng();
-    std::iter::repeat_with(|| rng.sample(Alphanumeric))
-        .map(char::from)
-        .take(25)
-        .collect()
-}
@@ -0,0 +1,125 @@
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::{Executor, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    errors::PreferencesError,
+    routes::{
+        pages::{escape_html, landing_page},
+        preferences::{invalid_link, PreferencesParameters},
+        subscriptions::{wants_json, SubscriptionStatus},
+    },
+    subscription_tokens::TokenHasher,
+};
+
+/// Ask for confirmation before unsubscribing. Following the link must not be
+/// enough: mail scanners follow every link in an email.
+#[tracing::instrument(
+    name = "Showing the unsubscribe page",
+    skip(request, parameters, pool, token_hasher)
+)]
+pub async fn unsubscribe_form(
+    request: HttpRequest,
+    parameters: web::Query<PreferencesParameters>,
+    pool: web::Data<PgPool>,
+    token_hasher: web::Data<TokenHasher>,
+) -> Result<HttpResponse, PreferencesError> {
+    let Some(subscriber_id) = token_hasher.verify_preferences_token(&parameters.token) else {
+        return invalid_link(&request);
+    };
+    let Some(subscriber) = sqlx::query!(
+        "SELECT display_email, status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to retrieve the subscriber.")?
+    else {
+        return invalid_link(&request);
+    };
+    if subscriber.status == "unsubscribed" {
+        return Ok(unsubscribed_page());
+    }
+    let content = format!(
+        r#"<p>Stop sending every email to {email}?</p>
+    <form action="/unsubscribe?token={token}" method="post">
+        <p><button type="submit">Unsubscribe</button></p>
+    </form>
+    <p>You can also <a href="/preferences?token={token}">pick what you receive</a> instead.</p>"#,
+        email = escape_html(&subscriber.display_email),
+        token = escape_html(&parameters.token),
+    );
+    Ok(landing_page(StatusCode::OK, "Unsubscribe", &content))
+}
+
+/// Stop sending anything to a subscriber. Also answers the one-click
+/// unsubscribe requests of mail clients, see RFC 8058.
+#[tracing::instrument(
+    name = "Unsubscribing a subscriber",
+    skip(request, parameters, pool, token_hasher)
+)]
+pub async fn unsubscribe(
+    request: HttpRequest,
+    parameters: web::Query<PreferencesParameters>,
+    pool: web::Data<PgPool>,
+    token_hasher: web::Data<TokenHasher>,
+) -> Result<HttpResponse, PreferencesError> {
+    let Some(subscriber_id) = token_hasher.verify_preferences_token(&parameters.token) else {
+        return invalid_link(&request);
+    };
+    if !unsubscribe_subscriber(&pool, subscriber_id).await? {
+        return invalid_link(&request);
+    }
+    if wants_json(&request) {
+        return Ok(HttpResponse::Ok().json(SubscriptionStatus {
+            status: "unsubscribed",
+        }));
+    }
+    Ok(unsubscribed_page())
+}
+
+/// Mark a subscriber as unsubscribed, and drop whatever could still reach or
+/// confirm them. `false` if they no longer exist.
+#[tracing::instrument(name = "Marking subscriber as unsubscribed", skip(pool))]
+async fn unsubscribe_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<bool, anyhow::Error> {
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let Some(email) = sqlx::query_scalar!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1 RETURNING email",
+        subscriber_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to update the subscriber.")?
+    else {
+        return Ok(false);
+    };
+    // An old confirmation link would subscribe them again.
+    tx.execute(sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    ))
+    .await
+    .context("Failed to delete the confirmation tokens.")?;
+    tx.execute(sqlx::query!(
+        "DELETE FROM issue_deliveries WHERE subscriber_email = $1 AND status = 'pending'",
+        email
+    ))
+    .await
+    .context("Failed to cancel the pending deliveries.")?;
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe.")?;
+    Ok(true)
+}
+
+fn unsubscribed_page() -> HttpResponse {
+    landing_page(
+        StatusCode::OK,
+        "You are unsubscribed",
+        r#"<p>We will not email you anymore. Changed your mind? <a href="/subscriptions">Subscribe again</a>.</p>"#,
+    )
+}
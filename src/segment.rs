@@ -0,0 +1,505 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{Postgres, QueryBuilder};
+
+/// Longer segments are rejected rather than parsed.
+const MAX_LENGTH: usize = 2000;
+/// How deeply parentheses and `NOT` may nest.
+const MAX_DEPTH: usize = 16;
+
+/// An audience filter on what we know about subscribers, e.g.
+/// `tag in [rust, async] AND subscribed_at > 2025-01-01`. See
+/// [`Segment::parse`] for the syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    And(Box<Segment>, Box<Segment>),
+    Or(Box<Segment>, Box<Segment>),
+    Not(Box<Segment>),
+    Condition(Condition),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// Tagged with any of these.
+    Tag(Vec<String>),
+    /// Signed up from any of these sources.
+    Source(Vec<String>),
+    /// Has the custom attribute `key` set to any of `values`.
+    Attribute {
+        key: String,
+        values: Vec<String>,
+    },
+    SubscribedAt(Comparison, DateTime<Utc>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Before,
+    AtOrBefore,
+    After,
+    AtOrAfter,
+}
+
+impl Comparison {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            Comparison::Before => "<",
+            Comparison::AtOrBefore => "<=",
+            Comparison::After => ">",
+            Comparison::AtOrAfter => ">=",
+        }
+    }
+}
+
+/// Why a segment could not be parsed.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("Invalid segment: {0}")]
+pub struct SegmentError(String);
+
+impl Segment {
+    /// Conditions combined with `AND`, `OR`, `NOT` and parentheses. A
+    /// condition is `tag`, `source` or `attr.<key>` followed by `=`, `!=`,
+    /// `in [...]` or `not in [...]` and bare or double-quoted values, or
+    /// `subscribed_at` compared with `<`, `<=`, `>` or `>=` to a date or an
+    /// RFC 3339 timestamp.
+    pub fn parse(segment: &str) -> Result<Self, SegmentError> {
+        if segment.len() > MAX_LENGTH {
+            return Err(SegmentError(format!(
+                "segments must be at most {MAX_LENGTH} characters long"
+            )));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(segment)?,
+            position: 0,
+            depth: 0,
+        };
+        let segment = parser.or()?;
+        match parser.next() {
+            None => Ok(segment),
+            Some(token) => Err(SegmentError(format!(
+                "unexpected {token} after a condition"
+            ))),
+        }
+    }
+
+    /// Append the segment to `query` as a condition on the subscriber `s`.
+    pub fn push_sql(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Segment::And(left, right) | Segment::Or(left, right) => {
+                let operator = if matches!(self, Segment::And(..)) {
+                    " AND "
+                } else {
+                    " OR "
+                };
+                query.push("(");
+                left.push_sql(query);
+                query.push(operator);
+                right.push_sql(query);
+                query.push(")");
+            }
+            Segment::Not(segment) => {
+                query.push("NOT ");
+                segment.push_sql(query);
+            }
+            Segment::Condition(condition) => condition.push_sql(query),
+        }
+    }
+}
+
+impl Condition {
+    fn push_sql(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Condition::Tag(tags) => {
+                query
+                    .push(
+                        "EXISTS (SELECT 1 FROM subscriber_tags st \
+                        WHERE st.subscriber_id = s.id AND st.tag = ANY(",
+                    )
+                    .push_bind(tags.clone())
+                    .push("))");
+            }
+            Condition::Source(sources) => {
+                // `COALESCE`, so that `NOT` includes subscribers without a source.
+                query
+                    .push("COALESCE(s.signup_source = ANY(")
+                    .push_bind(sources.clone())
+                    .push("), FALSE)");
+            }
+            Condition::Attribute { key, values } => {
+                query
+                    .push(
+                        "EXISTS (SELECT 1 FROM subscriber_attributes sa \
+                        WHERE sa.subscriber_id = s.id AND sa.key = ",
+                    )
+                    .push_bind(key.clone())
+                    .push(" AND sa.value = ANY(")
+                    .push_bind(values.clone())
+                    .push("))");
+            }
+            Condition::SubscribedAt(comparison, at) => {
+                query
+                    .push("s.subscribed_at ")
+                    .push(comparison.as_sql())
+                    .push(" ")
+                    .push_bind(*at);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Operator(&'static str),
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    Comma,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{word}`"),
+            Token::Quoted(value) => write!(f, "\"{value}\""),
+            Token::Operator(operator) => write!(f, "`{operator}`"),
+            Token::OpenParen => write!(f, "`(`"),
+            Token::CloseParen => write!(f, "`)`"),
+            Token::OpenBracket => write!(f, "`[`"),
+            Token::CloseBracket => write!(f, "`]`"),
+            Token::Comma => write!(f, "`,`"),
+        }
+    }
+}
+
+fn is_word_character(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':' | '+')
+}
+
+fn tokenize(segment: &str) -> Result<Vec<Token>, SegmentError> {
+    let mut tokens = Vec::new();
+    let mut chars = segment.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            '[' => Token::OpenBracket,
+            ']' => Token::CloseBracket,
+            ',' => Token::Comma,
+            '=' => Token::Operator("="),
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Operator("!="),
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Operator("<="),
+            '<' => Token::Operator("<"),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Operator(">="),
+            '>' => Token::Operator(">"),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => value.push(c),
+                            None => return Err(SegmentError("unterminated string".into())),
+                        },
+                        Some(c) => value.push(c),
+                        None => return Err(SegmentError("unterminated string".into())),
+                    }
+                }
+                Token::Quoted(value)
+            }
+            c if is_word_character(c) => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| is_word_character(*c)) {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+            c => return Err(SegmentError(format!("unexpected character `{c}`"))),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Consume the next token if it is the keyword `keyword`, in any case.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), SegmentError> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(SegmentError(format!("expected {expected}, found {token}"))),
+            None => Err(SegmentError(format!(
+                "expected {expected}, found the end of the segment"
+            ))),
+        }
+    }
+
+    fn or(&mut self) -> Result<Segment, SegmentError> {
+        let mut segment = self.and()?;
+        while self.keyword("or") {
+            segment = Segment::Or(Box::new(segment), Box::new(self.and()?));
+        }
+        Ok(segment)
+    }
+
+    fn and(&mut self) -> Result<Segment, SegmentError> {
+        let mut segment = self.unary()?;
+        while self.keyword("and") {
+            segment = Segment::And(Box::new(segment), Box::new(self.unary()?));
+        }
+        Ok(segment)
+    }
+
+    fn unary(&mut self) -> Result<Segment, SegmentError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(SegmentError(format!(
+                "segments must not nest more than {MAX_DEPTH} levels deep"
+            )));
+        }
+        let segment = if self.keyword("not") {
+            Segment::Not(Box::new(self.unary()?))
+        } else if self.peek() == Some(&Token::OpenParen) {
+            self.position += 1;
+            let segment = self.or()?;
+            self.expect(Token::CloseParen)?;
+            segment
+        } else {
+            self.condition()?
+        };
+        self.depth -= 1;
+        Ok(segment)
+    }
+
+    fn condition(&mut self) -> Result<Segment, SegmentError> {
+        let field = match self.next() {
+            Some(Token::Word(field)) => field,
+            Some(token) => {
+                return Err(SegmentError(format!(
+                    "expected a field such as `tag`, found {token}"
+                )))
+            }
+            None => {
+                return Err(SegmentError(
+                    "expected a condition, found the end of the segment".into(),
+                ))
+            }
+        };
+        let field = field.to_ascii_lowercase();
+        if field == "subscribed_at" {
+            let comparison = match self.next() {
+                Some(Token::Operator("<")) => Comparison::Before,
+                Some(Token::Operator("<=")) => Comparison::AtOrBefore,
+                Some(Token::Operator(">")) => Comparison::After,
+                Some(Token::Operator(">=")) => Comparison::AtOrAfter,
+                _ => {
+                    return Err(SegmentError(
+                        "`subscribed_at` must be compared with `<`, `<=`, `>` or `>=`".into(),
+                    ))
+                }
+            };
+            let at = self.value()?;
+            return Ok(Segment::Condition(Condition::SubscribedAt(
+                comparison,
+                parse_timestamp(&at)?,
+            )));
+        }
+
+        let (values, negated) = self.values(&field)?;
+        let condition = match field.as_str() {
+            "tag" => Condition::Tag(values),
+            "source" => Condition::Source(values),
+            field => match field.strip_prefix("attr.") {
+                Some(key) if !key.is_empty() => Condition::Attribute {
+                    key: key.to_string(),
+                    values,
+                },
+                _ => {
+                    return Err(SegmentError(format!(
+                        "unknown field `{field}`, expected `tag`, `source`, `subscribed_at` \
+                        or `attr.<key>`"
+                    )))
+                }
+            },
+        };
+        let condition = Segment::Condition(condition);
+        Ok(if negated {
+            Segment::Not(Box::new(condition))
+        } else {
+            condition
+        })
+    }
+
+    /// The values a text field is compared to, and whether the comparison is
+    /// negated.
+    fn values(&mut self, field: &str) -> Result<(Vec<String>, bool), SegmentError> {
+        match self.next() {
+            Some(Token::Operator("=")) => Ok((vec![self.value()?], false)),
+            Some(Token::Operator("!=")) => Ok((vec![self.value()?], true)),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("in") => Ok((self.list()?, false)),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("not") && self.keyword("in") => {
+                Ok((self.list()?, true))
+            }
+            _ => Err(SegmentError(format!(
+                "`{field}` must be followed by `=`, `!=`, `in` or `not in`"
+            ))),
+        }
+    }
+
+    fn list(&mut self) -> Result<Vec<String>, SegmentError> {
+        self.expect(Token::OpenBracket)?;
+        let mut values = vec![self.value()?];
+        while self.peek() == Some(&Token::Comma) {
+            self.position += 1;
+            values.push(self.value()?);
+        }
+        self.expect(Token::CloseBracket)?;
+        Ok(values)
+    }
+
+    fn value(&mut self) -> Result<String, SegmentError> {
+        match self.next() {
+            Some(Token::Word(value) | Token::Quoted(value)) => Ok(value),
+            Some(token) => Err(SegmentError(format!("expected a value, found {token}"))),
+            None => Err(SegmentError(
+                "expected a value, found the end of the segment".into(),
+            )),
+        }
+    }
+}
+
+/// A date, meaning midnight UTC, or an RFC 3339 timestamp.
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, SegmentError> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_time(Default::default()).and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|at| at.with_timezone(&Utc))
+        .map_err(|_| {
+            SegmentError(format!(
+                "`{value}` is not a date (2025-01-01) or an RFC 3339 timestamp"
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use claims::assert_err;
+    use sqlx::{Postgres, QueryBuilder};
+
+    use super::{Comparison, Condition, Segment};
+
+    fn tag(tags: &[&str]) -> Segment {
+        Segment::Condition(Condition::Tag(tags.iter().map(|t| t.to_string()).collect()))
+    }
+
+    #[test]
+    fn conditions_combine_with_and_binding_tighter_than_or() {
+        let segment =
+            Segment::parse("tag in [rust, async] AND subscribed_at > 2025-01-01 or tag = go")
+                .unwrap();
+
+        assert_eq!(
+            segment,
+            Segment::Or(
+                Box::new(Segment::And(
+                    Box::new(tag(&["rust", "async"])),
+                    Box::new(Segment::Condition(Condition::SubscribedAt(
+                        Comparison::After,
+                        Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap()
+                    )))
+                )),
+                Box::new(tag(&["go"]))
+            )
+        );
+    }
+
+    #[test]
+    fn negations_and_quoted_values_are_supported() {
+        let segment =
+            Segment::parse(r#"NOT (source != "landing page" OR attr.plan not in [free])"#).unwrap();
+
+        assert_eq!(
+            segment,
+            Segment::Not(Box::new(Segment::Or(
+                Box::new(Segment::Not(Box::new(Segment::Condition(
+                    Condition::Source(vec!["landing page".into()])
+                )))),
+                Box::new(Segment::Not(Box::new(Segment::Condition(
+                    Condition::Attribute {
+                        key: "plan".into(),
+                        values: vec!["free".into()]
+                    }
+                ))))
+            )))
+        );
+    }
+
+    #[test]
+    fn values_are_bound_rather_than_inlined() {
+        let segment = Segment::parse(r#"tag = "x'); DROP TABLE subscriptions; --""#).unwrap();
+        let mut query = QueryBuilder::<Postgres>::new("");
+        segment.push_sql(&mut query);
+
+        assert_eq!(
+            query.sql(),
+            "EXISTS (SELECT 1 FROM subscriber_tags st WHERE st.subscriber_id = s.id \
+            AND st.tag = ANY($1))"
+        );
+    }
+
+    #[test]
+    fn invalid_segments_are_rejected() {
+        for segment in [
+            "",
+            "tag",
+            "tag in [rust",
+            "tag in []",
+            "(tag = rust",
+            "tag = rust AND",
+            "tag = rust tag = go",
+            "email = someone@example.com",
+            "subscribed_at = 2025-01-01",
+            "subscribed_at > yesterday",
+            "tag = \"unterminated",
+            "tag ~ rust",
+        ] {
+            assert_err!(Segment::parse(segment), "{segment}");
+        }
+    }
+
+    #[test]
+    fn deeply_nested_segments_are_rejected() {
+        let segment = format!("{}tag = rust{}", "(".repeat(50), ")".repeat(50));
+
+        assert_err!(Segment::parse(&segment));
+    }
+}
@@ -1,38 +1,57 @@
-use std::net::TcpListener;
+use std::{net::TcpListener, sync::Arc};
 
 use crate::{
-    configuration::{DatabaseSettings, Settings},
-    email_client::EmailClient,
-    routes::{confirm, health_check, publish_newsletter, subscribe},
+    authentication::AdminToken,
+    bot_protection::BotProtection,
+    configuration::{
+        ArchiveSettings, ConfirmationSettings, DatabaseSettings, RuntimeSettings, Settings,
+        SharedRuntimeSettings, Topics,
+    },
+    deliverability::DeliverabilityCheck,
+    migrations::run_migrations,
+    problem::{correlate, form_config, json_config, query_config},
+    rate_limit::{rate_limit_by_ip, RateLimiter},
+    routes::{
+        archive, archived_issue, atom_feed, confirm, delete_log_filter, get_log_filter,
+        health_check, json_feed, newsletter_dry_run, preferences_form, preview_draft,
+        publish_newsletter, put_log_filter, resend_confirmation, rss_feed, send_test_draft,
+        subscribe, subscription_form, subscription_form_token, unsubscribe, unsubscribe_form,
+        update_preferences,
+    },
+    subscription_tokens::TokenHasher,
+    telemetry::LogFilter,
 };
-use actix_web::{dev::Server, web, App, HttpServer};
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use actix_web::{dev::Server, middleware::from_fn, web, App, HttpServer};
+use anyhow::Context;
+use secrecy::SecretString;
+use sqlx::PgPool;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
     port: u16,
     server: Server,
+    runtime_settings: Arc<SharedRuntimeSettings>,
 }
 
 impl Application {
-    pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
+    pub async fn build(
+        configuration: Settings,
+        log_filter: LogFilter,
+    ) -> Result<Self, anyhow::Error> {
         // Get configuration settings for database and application host address
-        let connection_pool = PgPool::connect_lazy_with(configuration.database.connect_options());
+        let connection_pool = get_connection_pool(&configuration.database);
 
-        // Build email `EmailClient` using `configuration`
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
+        if configuration.database.auto_migrate {
+            run_migrations(&connection_pool)
+                .await
+                .context("Failed to apply database migrations on startup.")?;
+        }
 
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        )
-        .expect("Failed to build email client.");
+        // Build the settings that can be reloaded while the server is running,
+        // e.g. the `EmailClient`.
+        let runtime_settings = Arc::new(SharedRuntimeSettings::new(
+            RuntimeSettings::new(&configuration).context("Failed to build email client.")?,
+        ));
 
         // Listen at address and run app
         let address = format!(
@@ -41,20 +60,46 @@ impl Application {
         );
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
+        let bot_protection = BotProtection::from_settings(&configuration.bot_protection)
+            .context("Failed to set up bot protection.")?;
+        let deliverability = DeliverabilityCheck::from_settings(&configuration.deliverability)
+            .context("Failed to set up the deliverability check.")?;
+        let rate_limiter =
+            RateLimiter::new(configuration.rate_limit.store, connection_pool.clone());
+        let token_hasher = TokenHasher::from_settings(&configuration.confirmation)?;
         let server = run(
             listener,
             connection_pool,
-            email_client,
+            rate_limiter,
+            bot_protection,
+            deliverability,
+            runtime_settings.clone(),
+            configuration.confirmation,
+            token_hasher,
+            Topics(configuration.topics),
+            configuration.archive,
             configuration.application.base_url,
+            configuration.application.admin_token,
+            log_filter,
         )?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            runtime_settings,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// The settings the running server picks up changes to, see
+    /// [`SettingsReloader`](crate::configuration::SettingsReloader).
+    pub fn runtime_settings(&self) -> Arc<SharedRuntimeSettings> {
+        self.runtime_settings.clone()
+    }
+
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
         self.server.await
     }
@@ -62,25 +107,107 @@ impl Application {
 
 pub struct ApplicationBaseUrl(pub String);
 
+#[allow(clippy::too_many_arguments)]
 fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
+    rate_limiter: RateLimiter,
+    bot_protection: Option<BotProtection>,
+    deliverability: Option<DeliverabilityCheck>,
+    runtime_settings: Arc<SharedRuntimeSettings>,
+    confirmation: ConfirmationSettings,
+    token_hasher: TokenHasher,
+    topics: Topics,
+    archive_settings: ArchiveSettings,
     base_url: String,
+    admin_token: Option<SecretString>,
+    log_filter: LogFilter,
 ) -> Result<Server, std::io::Error> {
     let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let admin_token = web::Data::new(AdminToken(admin_token));
+    let log_filter = web::Data::new(log_filter);
     let db_pool = web::Data::new(db_pool);
-    let email_client = web::Data::new(email_client);
+    let rate_limiter = web::Data::new(rate_limiter);
+    let bot_protection = bot_protection.map(web::Data::new);
+    let deliverability = deliverability.map(web::Data::new);
+    let runtime_settings = web::Data::from(runtime_settings);
+    let confirmation = web::Data::new(confirmation);
+    let token_hasher = web::Data::new(token_hasher);
+    let topics = web::Data::new(topics);
+    let archive_settings = web::Data::new(archive_settings);
     let server = HttpServer::new(move || {
-        App::new()
+        let app = App::new();
+        // Handlers take `Option<web::Data<_>>` for optional checks: they are
+        // only registered when enabled.
+        let app = match &bot_protection {
+            Some(bot_protection) => app.app_data(bot_protection.clone()),
+            None => app,
+        };
+        let app = match &deliverability {
+            Some(deliverability) => app.app_data(deliverability.clone()),
+            None => app,
+        };
+        app.wrap(from_fn(correlate))
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
+            .service(
+                web::resource("/subscriptions")
+                    .name("subscribe")
+                    .route(web::get().to(subscription_form))
+                    .route(web::post().to(subscribe).wrap(from_fn(rate_limit_by_ip))),
+            )
+            .route(
+                "/subscriptions/form-token",
+                web::get().to(subscription_form_token),
+            )
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .service(
+                web::resource("/subscriptions/resend-confirmation")
+                    .name("resend_confirmation")
+                    .route(
+                        web::post()
+                            .to(resend_confirmation)
+                            .wrap(from_fn(rate_limit_by_ip)),
+                    ),
+            )
+            .service(
+                web::resource("/preferences")
+                    .route(web::get().to(preferences_form))
+                    .route(web::post().to(update_preferences)),
+            )
+            .service(
+                web::resource("/unsubscribe")
+                    .route(web::get().to(unsubscribe_form))
+                    .route(web::post().to(unsubscribe)),
+            )
+            .route("/archive", web::get().to(archive))
+            .route("/archive/{slug}", web::get().to(archived_issue))
+            .route("/feed.xml", web::get().to(atom_feed))
+            .route("/rss.xml", web::get().to(rss_feed))
+            .route("/feed.json", web::get().to(json_feed))
             .route("/newsletters", web::post().to(publish_newsletter))
+            .route("/newsletters/dry-run", web::post().to(newsletter_dry_run))
+            .route("/admin/drafts/preview", web::post().to(preview_draft))
+            .route("/admin/drafts/test-send", web::post().to(send_test_draft))
+            .service(
+                web::resource("/admin/log_filter")
+                    .route(web::get().to(get_log_filter))
+                    .route(web::put().to(put_log_filter))
+                    .route(web::delete().to(delete_log_filter)),
+            )
+            .app_data(form_config())
+            .app_data(json_config())
+            .app_data(query_config())
             .app_data(db_pool.clone())
-            .app_data(email_client.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(runtime_settings.clone())
+            .app_data(confirmation.clone())
+            .app_data(token_hasher.clone())
+            .app_data(topics.clone())
+            .app_data(archive_settings.clone())
             .app_data(base_url.clone())
+            .app_data(admin_token.clone())
+            .app_data(log_filter.clone())
     })
     .listen(listener)?
     .run();
@@ -89,5 +216,7 @@ fn run(
 }
 
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
-    PgPoolOptions::new().connect_lazy_with(configuration.connect_options())
+    configuration
+        .pool_options()
+        .connect_lazy_with(configuration.connect_options())
 }
@@ -0,0 +1,178 @@
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, RngCore};
+use secrecy::{ExposeSecret, SecretString};
+use uuid::Uuid;
+
+use crate::configuration::ConfirmationSettings;
+
+/// Random bytes in a token, hex-encoded in confirmation links.
+const TOKEN_BYTES: usize = 32;
+
+pub fn generate_subscription_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Hashes subscription tokens with HMAC-SHA256 before they are stored, so that
+/// reading the database is not enough to confirm subscriptions.
+pub struct TokenHasher {
+    key: SecretString,
+}
+
+impl TokenHasher {
+    pub fn new(key: SecretString) -> Self {
+        Self { key }
+    }
+
+    pub fn from_settings(settings: &ConfirmationSettings) -> Result<Self, anyhow::Error> {
+        let key = settings
+            .token_key
+            .clone()
+            .context("Hashing subscription tokens requires `confirmation.token_key`.")?;
+        Ok(Self::new(key))
+    }
+
+    /// The hex-encoded hash to store and look tokens up by.
+    pub fn hash(&self, token: &str) -> String {
+        hex::encode(self.mac(token).finalize().into_bytes())
+    }
+
+    /// Whether `hash` is the hash of `token`, compared in constant time.
+    pub fn verify(&self, token: &str, hash: &str) -> bool {
+        hex::decode(hash).is_ok_and(|hash| self.mac(token).verify_slice(&hash).is_ok())
+    }
+
+    /// A token for the preferences page of a subscriber, sent with every
+    /// issue. It is signed rather than stored, so it stays valid until the key
+    /// changes.
+    pub fn preferences_token(&self, subscriber_id: Uuid) -> String {
+        format!(
+            "{subscriber_id}.{}",
+            self.hash(&preferences_message(subscriber_id))
+        )
+    }
+
+    /// The subscriber a preferences token was issued for, if it is genuine.
+    pub fn verify_preferences_token(&self, token: &str) -> Option<Uuid> {
+        let (subscriber_id, signature) = token.split_once('.')?;
+        let subscriber_id = Uuid::parse_str(subscriber_id).ok()?;
+        self.verify(&preferences_message(subscriber_id), signature)
+            .then_some(subscriber_id)
+    }
+
+    fn mac(&self, token: &str) -> Hmac<sha2::Sha256> {
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(self.key.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(token.as_bytes());
+        mac
+    }
+}
+
+/// What preferences tokens sign, prefixed so that they can never be mistaken
+/// for the hash of a subscription token.
+fn preferences_message(subscriber_id: Uuid) -> String {
+    format!("preferences:{subscriber_id}")
+}
+
+/// The link to the preferences page of a subscriber.
+pub fn preferences_url(base_url: &str, token: &str) -> String {
+    format!("{base_url}/preferences?token={token}")
+}
+
+/// The link to stop all emails to a subscriber. It takes the same token as
+/// the preferences page.
+pub fn unsubscribe_url(base_url: &str, token: &str) -> String {
+    format!("{base_url}/unsubscribe?token={token}")
+}
+
+/// The links every email to a subscriber ends with.
+pub struct SubscriberLinks {
+    pub preferences: String,
+    pub unsubscribe: String,
+}
+
+impl SubscriberLinks {
+    pub fn new(base_url: &str, token_hasher: &TokenHasher, subscriber_id: Uuid) -> Self {
+        let token = token_hasher.preferences_token(subscriber_id);
+        Self {
+            preferences: preferences_url(base_url, &token),
+            unsubscribe: unsubscribe_url(base_url, &token),
+        }
+    }
+
+    pub fn html_footer(&self) -> String {
+        format!(
+            r#"<p><a href="{}">Manage your preferences</a> | <a href="{}">Unsubscribe</a></p>"#,
+            self.preferences, self.unsubscribe
+        )
+    }
+
+    pub fn text_footer(&self) -> String {
+        format!(
+            "Manage your preferences: {}\nUnsubscribe: {}",
+            self.preferences, self.unsubscribe
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{generate_subscription_token, TokenHasher};
+
+    fn hasher(key: &str) -> TokenHasher {
+        TokenHasher::new(key.into())
+    }
+
+    #[test]
+    fn tokens_carry_256_bits_of_randomness() {
+        let token = generate_subscription_token();
+
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, generate_subscription_token());
+    }
+
+    #[test]
+    fn a_token_matches_its_own_hash_only() {
+        let hasher = hasher("a-token-key-that-is-long-enough!");
+        let token = generate_subscription_token();
+        let hash = hasher.hash(&token);
+
+        assert_ne!(hash, token);
+        assert!(hasher.verify(&token, &hash));
+        assert!(!hasher.verify(&generate_subscription_token(), &hash));
+        assert!(!hasher.verify(&token, "not-hex"));
+    }
+
+    #[test]
+    fn hashes_depend_on_the_key() {
+        let token = generate_subscription_token();
+
+        assert_ne!(
+            hasher("a-token-key-that-is-long-enough!").hash(&token),
+            hasher("another-key-that-is-long-enough!").hash(&token)
+        );
+    }
+
+    #[test]
+    fn preferences_tokens_identify_their_subscriber() {
+        let token_hasher = hasher("a-token-key-that-is-long-enough!");
+        let subscriber_id = Uuid::new_v4();
+        let token = token_hasher.preferences_token(subscriber_id);
+
+        assert_eq!(
+            token_hasher.verify_preferences_token(&token),
+            Some(subscriber_id)
+        );
+        let forged = token.replacen(&subscriber_id.to_string(), &Uuid::new_v4().to_string(), 1);
+        assert_eq!(token_hasher.verify_preferences_token(&forged), None);
+        assert_eq!(
+            hasher("another-key-that-is-long-enough!").verify_preferences_token(&token),
+            None
+        );
+        assert_eq!(token_hasher.verify_preferences_token("garbage"), None);
+    }
+}
@@ -1,23 +1,35 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use tracing::{subscriber::set_global_default, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
-use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, reload, EnvFilter, Registry};
+
+/// Swaps the filter of a subscriber built by [`get_subscriber`] while it is running.
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
 
 pub fn get_subscriber<Sink>(
     name: impl Into<String>,
     env_filter: impl Into<String>,
     sink: Sink,
-) -> impl Subscriber + Send + Sync
+) -> (impl Subscriber + Send + Sync, LogFilterHandle)
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter.into()));
+    let (env_filter, handle) = reload::Layer::new(env_filter);
     let formatting_layer = BunyanFormattingLayer::new(name.into(), sink);
-    Registry::default()
+    let subscriber = Registry::default()
         .with(env_filter)
         .with(JsonStorageLayer)
-        .with(formatting_layer)
+        .with(formatting_layer);
+    (subscriber, handle)
 }
 
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
@@ -25,3 +37,144 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
+
+/// Controls the filter of the running subscriber: the one set by the
+/// configuration, and temporary overrides that revert on their own.
+#[derive(Clone)]
+pub struct LogFilter {
+    handle: LogFilterHandle,
+    state: Arc<Mutex<LogFilterState>>,
+}
+
+struct LogFilterState {
+    /// The filter the subscriber started with, from `RUST_LOG` or the default.
+    initial: String,
+    configured: Option<String>,
+    temporary: Option<TemporaryLogFilter>,
+    /// Bumped on every change, so a stale revert does not undo a newer one.
+    generation: u64,
+}
+
+struct TemporaryLogFilter {
+    directives: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// The filter in effect and, if it is temporary, when it reverts.
+#[derive(Serialize)]
+pub struct LogFilterStatus {
+    pub directives: String,
+    pub configured: String,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl LogFilter {
+    pub fn new(handle: LogFilterHandle) -> Self {
+        let initial = handle
+            .with_current(|filter| filter.to_string())
+            .unwrap_or_else(|_| "info".into());
+        Self {
+            handle,
+            state: Arc::new(Mutex::new(LogFilterState {
+                initial,
+                configured: None,
+                temporary: None,
+                generation: 0,
+            })),
+        }
+    }
+
+    pub fn status(&self) -> LogFilterStatus {
+        let state = self.state.lock().unwrap();
+        let configured = state.configured().to_string();
+        match &state.temporary {
+            Some(temporary) => LogFilterStatus {
+                directives: temporary.directives.clone(),
+                configured,
+                expires_at: Some(temporary.expires_at),
+            },
+            None => LogFilterStatus {
+                directives: configured.clone(),
+                configured,
+                expires_at: None,
+            },
+        }
+    }
+
+    /// Set the filter from the configuration, or go back to the initial one
+    /// if `None`. A temporary filter stays in effect until it expires.
+    pub fn configure(&self, directives: Option<&str>) -> Result<(), String> {
+        let filter = parse(directives.unwrap_or(&self.state.lock().unwrap().initial))?;
+        let mut state = self.state.lock().unwrap();
+        state.configured = directives.map(String::from);
+        if state.temporary.is_none() {
+            self.apply(filter)?;
+        }
+        Ok(())
+    }
+
+    /// Use `directives` for `ttl`, then revert to the configured filter.
+    pub fn set_temporarily(
+        &self,
+        directives: &str,
+        ttl: Duration,
+    ) -> Result<LogFilterStatus, String> {
+        let filter = parse(directives)?;
+        let expires_at = Utc::now() + ttl;
+        let generation = {
+            let mut state = self.state.lock().unwrap();
+            self.apply(filter)?;
+            state.generation += 1;
+            state.temporary = Some(TemporaryLogFilter {
+                directives: directives.to_string(),
+                expires_at,
+            });
+            state.generation
+        };
+        tracing::info!(directives, %expires_at, "Applied a temporary log filter.");
+
+        let log_filter = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(ttl).await;
+            if let Err(e) = log_filter.revert(Some(generation)) {
+                tracing::error!(error.message = %e, "Failed to revert the log filter.");
+            }
+        });
+
+        Ok(self.status())
+    }
+
+    /// Drop the temporary filter, if any, and go back to the configured one.
+    pub fn reset(&self) -> Result<LogFilterStatus, String> {
+        self.revert(None)?;
+        Ok(self.status())
+    }
+
+    /// Go back to the configured filter, unless `generation` is given and
+    /// the filter has changed since.
+    fn revert(&self, generation: Option<u64>) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if generation.is_some_and(|generation| generation != state.generation) {
+            return Ok(());
+        }
+        self.apply(parse(state.configured())?)?;
+        state.generation += 1;
+        state.temporary = None;
+        tracing::info!("Reverted to the configured log filter.");
+        Ok(())
+    }
+
+    fn apply(&self, filter: EnvFilter) -> Result<(), String> {
+        self.handle.reload(filter).map_err(|e| e.to_string())
+    }
+}
+
+impl LogFilterState {
+    fn configured(&self) -> &str {
+        self.configured.as_deref().unwrap_or(&self.initial)
+    }
+}
+
+fn parse(directives: &str) -> Result<EnvFilter, String> {
+    EnvFilter::try_new(directives).map_err(|e| format!("`{directives}` is not a valid filter: {e}"))
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn log_filter_rejects_requests_without_the_admin_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let url = format!("{}/admin/log_filter", app.address);
+
+    let test_cases = vec![
+        (client.get(&url), "no token"),
+        (client.get(&url).bearer_auth("not-the-token"), "wrong token"),
+        (
+            client
+                .put(&url)
+                .json(&serde_json::json!({ "directives": "debug" })),
+            "no token on update",
+        ),
+    ];
+
+    for (request, description) in test_cases {
+        // Act
+        let response = request.send().await.expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(
+            401,
+            response.status().as_u16(),
+            "The API did not reject a request with {}.",
+            description
+        );
+        assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+    }
+}
+
+#[tokio::test]
+async fn log_filter_can_be_changed_temporarily() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .put_log_filter(serde_json::json!({ "directives": "info,sqlx=debug" }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let status: Value = app.get_log_filter().await.json().await.unwrap();
+    assert_eq!(status["directives"], "info,sqlx=debug");
+    assert!(status["expires_at"].is_string());
+}
+
+#[tokio::test]
+async fn temporary_log_filter_reverts_after_its_ttl() {
+    // Arrange
+    let app = spawn_app().await;
+    let configured: Value = app.get_log_filter().await.json().await.unwrap();
+
+    // Act
+    app.put_log_filter(serde_json::json!({ "directives": "debug", "ttl_seconds": 1 }))
+        .await;
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    // Assert
+    let status: Value = app.get_log_filter().await.json().await.unwrap();
+    assert_eq!(status["directives"], configured["directives"]);
+    assert!(status["expires_at"].is_null());
+}
+
+#[tokio::test]
+async fn log_filter_returns_400_for_invalid_changes() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "directives": "sqlx=[[" }),
+            "invalid directives",
+        ),
+        (
+            serde_json::json!({ "directives": "debug", "ttl_seconds": 0 }),
+            "a zero ttl",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.put_log_filter(body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+}
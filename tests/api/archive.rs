@@ -0,0 +1,163 @@
+use serde_json::{json, Value};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish(app: &TestApp, title: &str, public: bool) {
+    app.post_newsletters(json!({
+        "title": title,
+        "content": {
+            "html": format!("<p>{title} as HTML</p>"),
+            "text": format!("{title} as text"),
+        },
+        "public": public,
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+#[tokio::test]
+async fn the_archive_lists_public_issues_only() {
+    // Arrange
+    let app = spawn_app().await;
+    publish(&app, "Hello & welcome", true).await;
+    publish(&app, "Board minutes", false).await;
+
+    // Act
+    let response = app.get_archive("/archive").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<a href="/archive/hello-welcome">Hello &amp; welcome</a>"#));
+    assert!(!html.contains("Board minutes"));
+}
+
+#[tokio::test]
+async fn public_issues_have_a_page_under_their_slug() {
+    // Arrange
+    let app = spawn_app().await;
+    publish(&app, "Hello & welcome", true).await;
+
+    // Act
+    let response = app.get_archive("/archive/hello-welcome").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<h1>Hello &amp; welcome</h1>"));
+    assert!(html.contains("<p>Hello & welcome as HTML</p>"));
+}
+
+#[tokio::test]
+async fn private_and_unknown_issues_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    publish(&app, "Board minutes", false).await;
+
+    for path in ["/archive/board-minutes", "/archive/no-such-issue"] {
+        // Act
+        let response = app.get_archive(path).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 404, "{path}");
+    }
+}
+
+#[tokio::test]
+async fn issues_with_the_same_title_get_different_slugs() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    publish(&app, "Weekly update", true).await;
+    publish(&app, "Weekly update", true).await;
+
+    // Assert
+    let slugs: Vec<_> = sqlx::query!("SELECT slug FROM newsletter_issues ORDER BY published_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved issues.")
+        .into_iter()
+        .map(|r| r.slug)
+        .collect();
+    assert_eq!(slugs[0], "weekly-update");
+    assert!(slugs[1].starts_with("weekly-update-"));
+    assert_ne!(slugs[0], slugs[1]);
+}
+
+#[tokio::test]
+async fn concurrent_issues_with_the_same_title_are_all_published() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    tokio::join!(
+        publish(&app, "Breaking news", true),
+        publish(&app, "Breaking news", true),
+        publish(&app, "Breaking news", true),
+    );
+
+    // Assert
+    let slugs = sqlx::query!(r#"SELECT COUNT(DISTINCT slug) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count saved issues.")
+        .count;
+    assert_eq!(slugs, 3);
+}
+
+#[tokio::test]
+async fn the_atom_and_rss_feeds_include_public_issues_only() {
+    // Arrange
+    let app = spawn_app().await;
+    publish(&app, "Hello & welcome", true).await;
+    publish(&app, "Board minutes", false).await;
+
+    for (path, content_type) in [
+        ("/feed.xml", "application/atom+xml; charset=utf-8"),
+        ("/rss.xml", "application/rss+xml; charset=utf-8"),
+    ] {
+        // Act
+        let response = app.get_archive(path).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["Content-Type"], content_type);
+        let xml = response.text().await.unwrap();
+        assert!(xml.contains("<title>Hello &amp; welcome</title>"), "{path}");
+        assert!(
+            xml.contains("&lt;p&gt;Hello &amp; welcome as HTML&lt;/p&gt;"),
+            "{path}"
+        );
+        assert!(xml.contains("/archive/hello-welcome"), "{path}");
+        assert!(!xml.contains("Board minutes"), "{path}");
+    }
+}
+
+#[tokio::test]
+async fn the_json_feed_lists_the_latest_public_issues_first() {
+    // Arrange
+    let app = spawn_app().await;
+    publish(&app, "First", true).await;
+    publish(&app, "Second", true).await;
+    publish(&app, "Board minutes", false).await;
+
+    // Act
+    let response = app.get_archive("/feed.json").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "application/feed+json");
+    let feed: Value = response.json().await.unwrap();
+    assert_eq!(feed["version"], "https://jsonfeed.org/version/1.1");
+    let items = feed["items"].as_array().unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0]["title"], "Second");
+    assert_eq!(items[0]["content_text"], "Second as text");
+    assert!(items[0]["url"]
+        .as_str()
+        .unwrap()
+        .ends_with("/archive/second"));
+    assert_eq!(items[1]["title"], "First");
+}
@@ -0,0 +1,204 @@
+use serde_json::{json, Value};
+use wiremock::{
+    matchers::{body_string_contains, method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::configuration::{CaptchaProvider, CaptchaSettings, Settings};
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+const BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+fn enable_bot_protection(configuration: &mut Settings, min_submit_seconds: u64) {
+    configuration.bot_protection.enabled = true;
+    configuration.bot_protection.signing_key = Some("a-signing-key-that-is-long-enough".into());
+    configuration.bot_protection.min_submit_seconds = min_submit_seconds;
+}
+
+async fn mock_email_server(app: &TestApp, expected_requests: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected_requests)
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn subscriber_count(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count subscriptions.")
+}
+
+#[tokio::test]
+async fn the_form_embeds_a_honeypot_and_a_token_when_enabled() {
+    // Arrange
+    let app = spawn_app_with(|c| enable_bot_protection(c, 0)).await;
+
+    // Act
+    let html = app.get_subscription_form().await;
+
+    // Assert
+    assert!(html.contains(r#"name="website""#));
+    assert!(html.contains(r#"name="form_token""#));
+}
+
+#[tokio::test]
+async fn the_form_has_no_bot_fields_when_disabled() {
+    // Arrange
+    let app = spawn_app_with(|_| {}).await;
+
+    // Act
+    let html = app.get_subscription_form().await;
+
+    // Assert
+    assert!(html.contains(r#"name="email""#));
+    assert!(!html.contains(r#"name="form_token""#));
+}
+
+#[tokio::test]
+async fn json_clients_subscribe_with_a_token_from_the_token_endpoint() {
+    // Arrange
+    let app = spawn_app_with(|c| enable_bot_protection(c, 0)).await;
+    let body: Value = reqwest::get(format!("{}/subscriptions/form-token", app.address))
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["captcha"], Value::Null);
+
+    // Act
+    let response = app
+        .post_subscriptions_json(&json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "form_token": body["form_token"],
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn the_token_endpoint_has_no_token_when_disabled() {
+    // Arrange
+    let app = spawn_app_with(|_| {}).await;
+
+    // Act
+    let response = reqwest::get(format!("{}/subscriptions/form-token", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body, json!({ "form_token": null, "captcha": null }));
+}
+
+#[tokio::test]
+async fn subscribe_accepts_a_form_submitted_with_a_valid_token() {
+    // Arrange
+    let app = spawn_app_with(|c| enable_bot_protection(c, 0)).await;
+    mock_email_server(&app, 1).await;
+    let token = app.get_form_token().await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!("{BODY}&form_token={token}"))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn subscribe_fakes_success_for_suspected_bots() {
+    // Arrange
+    let app = spawn_app_with(|c| enable_bot_protection(c, 60)).await;
+    mock_email_server(&app, 0).await;
+    let token = app.get_form_token().await;
+    let test_cases = vec![
+        (
+            format!("{BODY}&website=spam.example.com"),
+            "honeypot filled",
+        ),
+        (BODY.to_string(), "missing token"),
+        (format!("{BODY}&form_token=1.abcdef"), "forged token"),
+        (format!("{BODY}&form_token={token}"), "submitted instantly"),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_subscriptions(body).await;
+
+        // Assert
+        assert_eq!(
+            200,
+            response.status().as_u16(),
+            "The API did not fake success when the submission was {}.",
+            description
+        );
+    }
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn subscribe_checks_the_captcha_with_the_provider() {
+    // Arrange
+    let captcha_server = wiremock::MockServer::start().await;
+    let verify_url = format!("{}/siteverify", captcha_server.uri());
+    let app = spawn_app_with(|c| {
+        enable_bot_protection(c, 0);
+        c.bot_protection.captcha = Some(CaptchaSettings {
+            provider: CaptchaProvider::Turnstile,
+            site_key: "site-key".into(),
+            secret: "captcha-secret".into(),
+            verify_url: Some(verify_url),
+            timeout_milliseconds: 1000,
+        });
+    })
+    .await;
+    mock_email_server(&app, 1).await;
+    Mock::given(path("/siteverify"))
+        .and(body_string_contains("response=solved"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true
+        })))
+        .mount(&captcha_server)
+        .await;
+    Mock::given(path("/siteverify"))
+        .and(body_string_contains("response=unsolved"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": false
+        })))
+        .mount(&captcha_server)
+        .await;
+    let html = app.get_subscription_form().await;
+    assert!(html.contains(r#"data-sitekey="site-key""#));
+    let token = app.get_form_token().await;
+
+    // Act
+    let failed = app
+        .post_subscriptions(format!(
+            "{BODY}&form_token={token}&cf-turnstile-response=unsolved"
+        ))
+        .await;
+    assert_eq!(subscriber_count(&app).await, 0);
+    let solved = app
+        .post_subscriptions(format!(
+            "{BODY}&form_token={token}&cf-turnstile-response=solved"
+        ))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, failed.status().as_u16());
+    assert_eq!(200, solved.status().as_u16());
+    assert_eq!(subscriber_count(&app).await, 1);
+}
@@ -0,0 +1,196 @@
+use serde_json::{json, Value};
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::configuration::Topic;
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+/// A confirmed subscriber who switched to the weekly digest, with `preferences`
+/// on top.
+async fn create_digest_subscriber(app: &TestApp, preferences: Value) {
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to confirm the subscriber.");
+    sqlx::query!("DELETE FROM email_outbox")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to clear the outbox.");
+    let mut body = json!({
+        "name": "le guin",
+        "email_format": "html",
+        "frequency": "weekly_digest",
+    });
+    body.as_object_mut()
+        .unwrap()
+        .extend(preferences.as_object().unwrap().clone());
+    app.post_preferences_json(&app.preferences_token(EMAIL).await, &body)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+async fn publish(app: &TestApp, title: &str, topic: Option<&str>) {
+    app.post_newsletters(json!({
+        "title": title,
+        "content": {
+            "html": format!("<p>{title} as HTML</p>"),
+            "text": format!("{title} as text"),
+        },
+        "topic": topic,
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+/// Pretend the last digest went out `days` ago.
+async fn rewind_digest_cursor(app: &TestApp, days: i32) {
+    sqlx::query!(
+        "UPDATE subscriptions SET last_digest_at = last_digest_at - make_interval(days => $1)",
+        days
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to rewind the digest cursor.");
+}
+
+#[tokio::test]
+async fn digest_subscribers_get_the_issues_of_the_week_in_one_email() {
+    // Arrange
+    let app = spawn_app().await;
+    create_digest_subscriber(&app, json!({})).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish(&app, "First issue", None).await;
+    publish(&app, "Second issue", None).await;
+    rewind_digest_cursor(&app, 8).await;
+
+    // Act
+    let report = app.enqueue_due_digests().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(report.sent, 1);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], EMAIL);
+    assert_eq!(body["Subject"], "Your weekly digest: 2 new issues");
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("<p>First issue as HTML</p>"));
+    assert!(html.contains("<p>Second issue as HTML</p>"));
+    assert!(html.contains("/preferences?token="));
+    assert!(html.contains("/unsubscribe?token="));
+
+    // A second run has nothing to send until next week.
+    assert_eq!(app.enqueue_due_digests().await.sent, 0);
+}
+
+#[tokio::test]
+async fn digests_are_not_sent_before_a_week_has_passed() {
+    // Arrange
+    let app = spawn_app().await;
+    create_digest_subscriber(&app, json!({})).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    publish(&app, "First issue", None).await;
+    rewind_digest_cursor(&app, 6).await;
+
+    // Act
+    let report = app.enqueue_due_digests().await;
+
+    // Assert
+    assert_eq!(report.sent + report.skipped, 0);
+}
+
+#[tokio::test]
+async fn digests_with_nothing_new_are_skipped_and_the_cursor_moves_on() {
+    // Arrange
+    let app = spawn_app().await;
+    create_digest_subscriber(&app, json!({})).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    rewind_digest_cursor(&app, 8).await;
+
+    // Act
+    let report = app.enqueue_due_digests().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(report.skipped, 1);
+    let cursor = sqlx::query!(r#"SELECT last_digest_at AS "last_digest_at!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the digest cursor.")
+        .last_digest_at;
+    assert!(chrono::Utc::now() - cursor < chrono::Duration::minutes(1));
+}
+
+#[tokio::test]
+async fn digests_only_include_the_topics_picked_and_follow_the_format() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.topics = ["releases", "events"]
+            .map(|id| Topic {
+                id: id.into(),
+                name: id.into(),
+            })
+            .into()
+    })
+    .await;
+    create_digest_subscriber(
+        &app,
+        json!({"email_format": "plain_text", "topics": ["releases"]}),
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish(&app, "Release", Some("releases")).await;
+    publish(&app, "Meetup", Some("events")).await;
+    rewind_digest_cursor(&app, 8).await;
+
+    // Act
+    app.enqueue_due_digests().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Your weekly digest: Release");
+    assert!(body.get("HtmlBody").is_none());
+    assert!(!body["TextBody"].as_str().unwrap().contains("Meetup"));
+}
@@ -0,0 +1,207 @@
+use serde_json::{json, Value};
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_problem, spawn_app, TestApp};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn subscribe(app: &TestApp) -> String {
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.preferences_token(EMAIL).await
+}
+
+fn draft(extra: Value) -> Value {
+    let mut body = json!({
+        "title": "Draft title",
+        "content": {
+            "html": "<p>Draft body as HTML</p>",
+            "text": "Draft body as plain text",
+        },
+    });
+    body.as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    body
+}
+
+async fn assert_nothing_was_published(app: &TestApp) {
+    let issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    let deliveries = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_deliveries"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!((issues, deliveries), (0, 0));
+}
+
+#[tokio::test]
+async fn a_preview_is_the_email_the_subscriber_would_receive() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = subscribe(&app).await;
+
+    // Act
+    let response = app
+        .post_draft_preview(&draft(json!({ "subscriber_email": EMAIL })))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let preview: Value = response.json().await.unwrap();
+    assert_eq!(preview["subject"], "Draft title");
+    let html = preview["html_content"].as_str().unwrap();
+    assert!(html.starts_with("<p>Draft body as HTML</p>"));
+    assert!(html.contains(&format!("/preferences?token={token}")));
+    assert!(html.contains(&format!("/unsubscribe?token={token}")));
+    let text = preview["text_content"].as_str().unwrap();
+    assert!(text.contains(&format!("/preferences?token={token}")));
+    assert!(text.ends_with(&format!("/unsubscribe?token={token}")));
+    assert_nothing_was_published(&app).await;
+}
+
+#[tokio::test]
+async fn a_preview_follows_the_format_of_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = subscribe(&app).await;
+    app.post_preferences_json(
+        &token,
+        &json!({
+            "name": "le guin",
+            "email_format": "plain_text",
+            "frequency": "every_issue",
+        }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_draft_preview(&draft(json!({ "subscriber_email": EMAIL })))
+        .await;
+
+    // Assert
+    let preview: Value = response.json().await.unwrap();
+    assert_eq!(preview["html_content"], "");
+    assert!(preview["text_content"]
+        .as_str()
+        .unwrap()
+        .starts_with("Draft body as plain text"));
+}
+
+#[tokio::test]
+async fn sample_subscribers_are_found_regardless_of_case() {
+    // Arrange
+    let app = spawn_app().await;
+    subscribe(&app).await;
+
+    // Act
+    let response = app
+        .post_draft_preview(&draft(
+            json!({ "subscriber_email": "Ursula_Le_Guin@GMAIL.com" }),
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn previews_for_unknown_subscribers_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_draft_preview(&draft(json!({ "subscriber_email": EMAIL })))
+        .await;
+
+    // Assert
+    assert_problem(response, 404, "unknown-subscriber").await;
+}
+
+#[tokio::test]
+async fn drafts_require_the_admin_token() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for endpoint in ["preview", "test-send"] {
+        // Act
+        let response = reqwest::Client::new()
+            .post(format!("{}/admin/drafts/{endpoint}", app.address))
+            .json(&draft(
+                json!({ "subscriber_email": EMAIL, "recipients": [EMAIL] }),
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        assert_problem(response, 401, "missing-credentials").await;
+    }
+}
+
+#[tokio::test]
+async fn test_copies_are_sent_without_publishing_the_draft() {
+    // Arrange
+    let app = spawn_app().await;
+    subscribe(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_draft_test_send(&draft(json!({
+            "recipients": ["editor@example.com", "reviewer@example.com"],
+        })))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: Value = response.json().await.unwrap();
+    assert_eq!(report["sent"], 2);
+    let requests = app.email_server.received_requests().await.unwrap();
+    let sent: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(sent["Subject"], "[Test] Draft title");
+    assert_eq!(sent["HtmlBody"], "<p>Draft body as HTML</p>");
+    assert_nothing_was_published(&app).await;
+}
+
+#[tokio::test]
+async fn test_sends_with_invalid_recipients_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let too_many: Vec<_> = (0..11).map(|i| format!("editor{i}@example.com")).collect();
+    for recipients in [json!([]), json!(["not-an-email"]), json!(too_many)] {
+        // Act
+        let response = app
+            .post_draft_test_send(&draft(json!({ "recipients": recipients })))
+            .await;
+
+        // Assert
+        assert_problem(response, 400, "invalid-recipients").await;
+    }
+}
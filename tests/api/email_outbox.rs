@@ -0,0 +1,102 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+const BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+struct OutboxRow {
+    status: String,
+    attempts: i32,
+    last_error: Option<String>,
+}
+
+async fn outbox_row(app: &TestApp) -> OutboxRow {
+    sqlx::query_as!(
+        OutboxRow,
+        "SELECT status, attempts, last_error FROM email_outbox"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch the outbox.")
+}
+
+#[tokio::test]
+async fn subscribing_does_not_depend_on_the_email_api() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(BODY.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let row = outbox_row(&app).await;
+    assert_eq!(row.status, "pending");
+    assert_eq!(row.attempts, 1);
+    assert!(row.last_error.is_some());
+}
+
+#[tokio::test]
+async fn failed_emails_are_retried_once_due() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(BODY.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Act
+    // Not due yet: the retry is delayed.
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+    sqlx::query!("UPDATE email_outbox SET next_attempt_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 2);
+    let row = outbox_row(&app).await;
+    assert_eq!(row.status, "sent");
+    assert_eq!(row.attempts, 2);
+}
+
+#[tokio::test]
+async fn emails_are_given_up_on_after_the_maximum_number_of_attempts() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_outbox.max_attempts = 1).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(BODY.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let row = outbox_row(&app).await;
+    assert_eq!(row.status, "failed");
+    assert_eq!(row.attempts, 1);
+}
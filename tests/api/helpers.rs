@@ -1,28 +1,45 @@
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 
 use serde_json::Value;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use wiremock::MockServer;
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    configuration::{
+        get_configuration, DatabaseSettings, DigestSettings, EmailOutboxSettings, Settings,
+        SharedRuntimeSettings,
+    },
+    digest::{enqueue_due_digests, DigestReport},
+    email_client::EmailClient,
+    email_outbox::relay_pending_emails,
+    migrations::MIGRATOR,
     startup::{get_connection_pool, Application},
-    telemetry::{get_subscriber, init_subscriber},
+    subscription_tokens::TokenHasher,
+    telemetry::{get_subscriber, init_subscriber, LogFilter, LogFilterHandle},
 };
 
 // Ensure the `tracing` stack is only initialized once using `LazyLock`
-static TRACING: LazyLock<()> = LazyLock::new(|| {
+static TRACING: LazyLock<LogFilterHandle> = LazyLock::new(|| {
     let default_filter_level = "info";
     let subscriber_name = "test";
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let (subscriber, log_filter) =
+            get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
         init_subscriber(subscriber);
+        log_filter
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let (subscriber, log_filter) =
+            get_subscriber(subscriber_name, default_filter_level, std::io::sink);
         init_subscriber(subscriber);
+        log_filter
     }
 });
 
+/// A controller for the filter of the test subscriber.
+pub fn log_filter() -> LogFilter {
+    LogFilter::new(TRACING.clone())
+}
+
 /// Confirmation links embedded in the request to the email API
 pub struct ConfirmationLinks {
     pub html: reqwest::Url,
@@ -34,9 +51,39 @@ pub struct TestApp {
     pub port: u16,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub email_client: EmailClient,
+    pub runtime_settings: Arc<SharedRuntimeSettings>,
+    pub email_outbox: EmailOutboxSettings,
+    pub digest: DigestSettings,
+    pub token_hasher: TokenHasher,
+    pub admin_token: String,
 }
 
 impl TestApp {
+    /// Send the emails in the outbox, as the relay worker would. The server
+    /// spawned for tests does not run it.
+    pub async fn dispatch_all_pending_emails(&self) {
+        relay_pending_emails(
+            &self.db_pool,
+            &self.runtime_settings.current().email_client,
+            &self.email_outbox,
+        )
+        .await
+        .expect("Failed to relay the email outbox.");
+    }
+
+    /// Put the digests that are due in the outbox, as the digest worker would.
+    pub async fn enqueue_due_digests(&self) -> DigestReport {
+        enqueue_due_digests(
+            &self.db_pool,
+            &self.address,
+            &self.token_hasher,
+            &self.digest,
+        )
+        .await
+        .expect("Failed to put together the digests.")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body = serde_json::from_slice::<Value>(&email_request.body).unwrap();
 
@@ -68,9 +115,172 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    /// Subscribe with a JSON body, asking for a JSON response.
+    pub async fn post_subscriptions_json(&self, body: &Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Accept", "application/json")
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_resend_confirmation(&self, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/subscriptions/resend-confirmation",
+                &self.address
+            ))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// The token of the preferences link sent to `email`.
+    pub async fn preferences_token(&self, email: &str) -> String {
+        let subscriber_id = sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+            .fetch_one(&self.db_pool)
+            .await
+            .expect("Failed to fetch the subscriber.")
+            .id;
+        self.token_hasher.preferences_token(subscriber_id)
+    }
+
+    pub async fn get_preferences(&self, token: &str, accept: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/preferences", &self.address))
+            .query(&[("token", token)])
+            .header("Accept", accept)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Update preferences with a JSON body, asking for a JSON response.
+    pub async fn post_preferences_json(&self, token: &str, body: &Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/preferences", &self.address))
+            .query(&[("token", token)])
+            .header("Accept", "application/json")
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Submit the HTML preferences form.
+    pub async fn post_preferences_form(
+        &self,
+        token: &str,
+        fields: &[(&str, &str)],
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/preferences", &self.address))
+            .query(&[("token", token)])
+            .form(fields)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_unsubscribe(&self, token: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/unsubscribe", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_unsubscribe(&self, token: &str, accept: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/unsubscribe", &self.address))
+            .query(&[("token", token)])
+            .header("Accept", accept)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_subscription_form(&self) -> String {
+        reqwest::get(format!("{}/subscriptions", &self.address))
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// The signed token embedded in the subscription form.
+    pub async fn get_form_token(&self) -> String {
+        let html = self.get_subscription_form().await;
+        let (_, rest) = html
+            .split_once(r#"name="form_token" value=""#)
+            .expect("The form has no token.");
+        rest.split('"').next().unwrap().to_string()
+    }
+
+    pub async fn get_log_filter(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/log_filter", self.address))
+            .bearer_auth(&self.admin_token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_log_filter(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/admin/log_filter", self.address))
+            .bearer_auth(&self.admin_token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters_dry_run(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters/dry-run", self.address))
+            .bearer_auth(&self.admin_token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_draft_preview(&self, body: &Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/drafts/preview", self.address))
+            .bearer_auth(&self.admin_token)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_draft_test_send(&self, body: &Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/drafts/test-send", self.address))
+            .bearer_auth(&self.admin_token)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// `path` of the archive or one of the feeds.
+    pub async fn get_archive(&self, path: &str) -> reqwest::Response {
+        reqwest::get(format!("{}{path}", self.address))
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", self.address))
+            .post(format!("{}/newsletters", self.address))
             .json(&body)
             .send()
             .await
@@ -78,60 +288,105 @@ impl TestApp {
     }
 }
 
+/// Check that `response` is an `application/problem+json` error of the given
+/// type, and return its body.
+pub async fn assert_problem(response: reqwest::Response, status: u16, problem_type: &str) -> Value {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: Value = response.json().await.expect("The problem is not JSON.");
+    assert_eq!(problem["type"], problem_type);
+    assert_eq!(problem["status"], status);
+    assert!(
+        Uuid::parse_str(problem["correlation_id"].as_str().unwrap()).is_ok(),
+        "The problem has no correlation id."
+    );
+    problem
+}
+
 // Launch application in the background
 pub async fn spawn_app() -> TestApp {
-    LazyLock::force(&TRACING);
+    spawn_app_with(|_| {}).await
+}
 
+/// Launch the application with test-specific changes to its configuration.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     let email_server = MockServer::start().await;
+    let admin_token = Uuid::new_v4().simple().to_string();
 
     let configuration = {
         let mut c = get_configuration().expect("Failed to read configuration");
-        c.database.database_name = Uuid::new_v4().to_string();
+        c.database.database_name = Some(Uuid::new_v4().to_string());
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        c.application.admin_token = Some(admin_token.clone().into());
+        configure(&mut c);
         c
     };
 
     configure_database(&configuration.database).await;
 
     // Launch the server as a background task
-    let application = Application::build(configuration.clone())
+    let application = Application::build(configuration.clone(), log_filter())
         .await
         .expect("Failed to build application");
     let application_port = application.port();
     let address = format!("http://127.0.0.1:{}", application.port());
-    let _ = tokio::spawn(application.run_until_stopped());
+    let runtime_settings = application.runtime_settings();
+    tokio::spawn(application.run_until_stopped());
     TestApp {
         address,
         port: application_port,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
+        email_client: configuration
+            .email_client
+            .client()
+            .expect("Failed to build email client."),
+        runtime_settings,
+        email_outbox: configuration.email_outbox.clone(),
+        digest: configuration.digest.clone(),
+        token_hasher: TokenHasher::from_settings(&configuration.confirmation)
+            .expect("Failed to build the token hasher."),
+        admin_token,
     }
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
-    // Create database
-    let maintenance_settings = DatabaseSettings {
-        database_name: "postgres".to_string(),
-        username: "postgres".to_string(),
-        password: "password".into(),
-        ..config.clone()
-    };
-    let mut connection = PgConnection::connect_with(&maintenance_settings.connect_options())
-        .await
-        .expect("Failed to connect to Postgres.");
-    connection
-        .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
-        .await
-        .expect("Failed to create database.");
+    create_database(config).await;
 
     // Migrate database
     let connection_pool = PgPool::connect_with(config.connect_options())
         .await
         .expect("Failed to connect to Postgres.");
-    sqlx::migrate!("./migrations")
+    MIGRATOR
         .run(&connection_pool)
         .await
         .expect("Failed to migrate the database");
     connection_pool
 }
+
+/// Create an empty database, without running any migration.
+pub async fn create_database(config: &DatabaseSettings) {
+    let maintenance_settings = DatabaseSettings {
+        database_name: Some("postgres".to_string()),
+        username: Some("postgres".to_string()),
+        password: Some("password".into()),
+        ..config.clone()
+    };
+    let mut connection = PgConnection::connect_with(&maintenance_settings.connect_options())
+        .await
+        .expect("Failed to connect to Postgres.");
+    connection
+        .execute(
+            format!(
+                r#"CREATE DATABASE "{}";"#,
+                config.database_name.as_deref().unwrap()
+            )
+            .as_str(),
+        )
+        .await
+        .expect("Failed to create database.");
+}
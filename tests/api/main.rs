@@ -1,5 +1,17 @@
+mod admin_log_filter;
+mod archive;
+mod bot_protection;
+mod database;
+mod digest;
+mod drafts;
+mod email_outbox;
 mod health_check;
 mod helpers;
+mod migrations;
 mod newsletter;
+mod preferences;
+mod rate_limit;
+mod resend_confirmation;
 mod subscription_confirmations;
 mod subscriptions;
+mod unsubscribe;
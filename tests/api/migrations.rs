@@ -0,0 +1,190 @@
+use sqlx::{migrate::Migrator, PgPool};
+use uuid::Uuid;
+use zero2prod::{
+    configuration::{get_configuration, Settings},
+    migrations::{migration_status, run_migrations, MigrationState, MIGRATOR},
+    startup::{get_connection_pool, Application},
+};
+
+use crate::helpers::{create_database, log_filter};
+
+async fn empty_database_configuration() -> Settings {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.database_name = Some(Uuid::new_v4().to_string());
+    configuration.application.port = 0;
+    create_database(&configuration.database).await;
+    configuration
+}
+
+#[tokio::test]
+async fn migrations_are_pending_on_an_empty_database() {
+    // Arrange
+    let configuration = empty_database_configuration().await;
+    let db_pool = get_connection_pool(&configuration.database);
+
+    // Act
+    let status = migration_status(&db_pool).await.unwrap();
+
+    // Assert
+    assert!(!status.is_empty());
+    assert!(status.iter().all(|m| m.state == MigrationState::Pending));
+}
+
+#[tokio::test]
+async fn application_applies_migrations_on_startup_when_auto_migrate_is_enabled() {
+    // Arrange
+    let mut configuration = empty_database_configuration().await;
+    configuration.database.auto_migrate = true;
+    let db_pool = get_connection_pool(&configuration.database);
+
+    // Act
+    Application::build(configuration, log_filter())
+        .await
+        .expect("Failed to build application");
+
+    // Assert
+    let status = migration_status(&db_pool).await.unwrap();
+    assert!(status.iter().all(|m| m.state == MigrationState::Applied));
+}
+
+#[tokio::test]
+async fn application_does_not_migrate_when_auto_migrate_is_disabled() {
+    // Arrange
+    let mut configuration = empty_database_configuration().await;
+    configuration.database.auto_migrate = false;
+    let db_pool = get_connection_pool(&configuration.database);
+
+    // Act
+    Application::build(configuration, log_filter())
+        .await
+        .expect("Failed to build application");
+
+    // Assert
+    let status = migration_status(&db_pool).await.unwrap();
+    assert!(status.iter().all(|m| m.state == MigrationState::Pending));
+}
+
+/// Apply the migrations that predate `version`, as a database deployed
+/// before it would have.
+async fn migrate_to_before(db_pool: &PgPool, version: i64) {
+    let migrator = Migrator {
+        migrations: MIGRATOR
+            .iter()
+            .filter(|m| m.version < version)
+            .cloned()
+            .collect(),
+        ..Migrator::DEFAULT
+    };
+    migrator.run(db_pool).await.unwrap();
+}
+
+async fn insert_legacy_subscriber(db_pool: &PgPool, email: &str) {
+    sqlx::query(
+        "INSERT INTO subscriptions (id, name, email, subscribed_at, status) \
+         VALUES ($1, 'legacy', $2, now(), 'confirmed')",
+    )
+    .bind(Uuid::new_v4())
+    .bind(email)
+    .execute(db_pool)
+    .await
+    .unwrap();
+}
+
+const CANONICAL_EMAILS_MIGRATION: i64 = 20250310120000;
+
+#[tokio::test]
+async fn existing_emails_are_canonicalized_and_kept_for_display() {
+    // Arrange
+    let configuration = empty_database_configuration().await;
+    let db_pool = get_connection_pool(&configuration.database);
+    migrate_to_before(&db_pool, CANONICAL_EMAILS_MIGRATION).await;
+    insert_legacy_subscriber(&db_pool, " Ursula@Example.COM ").await;
+
+    // Act
+    run_migrations(&db_pool).await.unwrap();
+
+    // Assert
+    let saved = sqlx::query!("SELECT email, display_email FROM subscriptions")
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "Ursula@example.com");
+    assert_eq!(saved.display_email, "Ursula@Example.COM");
+}
+
+#[tokio::test]
+async fn emails_that_only_differ_in_case_block_the_migration_with_a_report() {
+    // Arrange
+    let configuration = empty_database_configuration().await;
+    let db_pool = get_connection_pool(&configuration.database);
+    migrate_to_before(&db_pool, CANONICAL_EMAILS_MIGRATION).await;
+    insert_legacy_subscriber(&db_pool, "Alice@Example.com").await;
+    insert_legacy_subscriber(&db_pool, "alice@example.com").await;
+    insert_legacy_subscriber(&db_pool, "bob@example.com").await;
+
+    // Act
+    let error = run_migrations(&db_pool).await.unwrap_err().to_string();
+
+    // Assert
+    assert!(error.contains("Alice@Example.com"), "{error}");
+    assert!(error.contains("alice@example.com"), "{error}");
+    assert!(!error.contains("bob@example.com"), "{error}");
+    let status = migration_status(&db_pool).await.unwrap();
+    assert!(status
+        .iter()
+        .any(|m| m.version == CANONICAL_EMAILS_MIGRATION && m.state == MigrationState::Pending));
+}
+
+#[tokio::test]
+async fn emails_that_only_differ_in_the_case_of_their_domain_are_reported_too() {
+    // Arrange
+    let configuration = empty_database_configuration().await;
+    let db_pool = get_connection_pool(&configuration.database);
+    migrate_to_before(&db_pool, CANONICAL_EMAILS_MIGRATION).await;
+    insert_legacy_subscriber(&db_pool, "alice@Example.com").await;
+    insert_legacy_subscriber(&db_pool, "alice@example.com ").await;
+
+    // Act
+    let error = run_migrations(&db_pool).await.unwrap_err().to_string();
+
+    // Assert
+    assert!(error.contains("share an email address"), "{error}");
+    assert!(error.contains("alice@Example.com"), "{error}");
+}
+
+const HASHED_TOKENS_MIGRATION: i64 = 20250401120000;
+
+#[tokio::test]
+async fn plain_text_tokens_are_dropped_when_tokens_become_hashed() {
+    // Arrange
+    let configuration = empty_database_configuration().await;
+    let db_pool = get_connection_pool(&configuration.database);
+    migrate_to_before(&db_pool, HASHED_TOKENS_MIGRATION).await;
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query(
+        "INSERT INTO subscriptions (id, name, email, display_email, subscribed_at, status) \
+         VALUES ($1, 'legacy', 'ursula@example.com', 'ursula@example.com', now(), 'pending_confirmation')",
+    )
+    .bind(subscriber_id)
+    .execute(&db_pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO subscription_tokens (subscription_token, subscriber_id) \
+         VALUES ('plaintexttoken', $1)",
+    )
+    .bind(subscriber_id)
+    .execute(&db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    run_migrations(&db_pool).await.unwrap();
+
+    // Assert
+    let tokens = sqlx::query!("SELECT token_hash FROM subscription_tokens")
+        .fetch_all(&db_pool)
+        .await
+        .unwrap();
+    assert!(tokens.is_empty());
+}
@@ -1,9 +1,12 @@
+use serde_json::Value;
 use wiremock::{
     matchers::{any, method, path},
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use zero2prod::{configuration::Topic, issue_delivery::retry_failed_deliveries};
+
+use crate::helpers::{assert_problem, spawn_app, spawn_app_with, ConfirmationLinks, TestApp};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -93,6 +96,358 @@ async fn newsletters_returns_400_for_invalid_data() {
     }
 }
 
+#[tokio::test]
+async fn failed_deliveries_are_recorded_and_can_be_retried() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text"
+        }
+    });
+
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        let response = app.post_newsletters(newsletter_request_body).await;
+        assert_eq!(response.status().as_u16(), 500);
+    }
+
+    let delivery =
+        sqlx::query!("SELECT newsletter_issue_id, status, attempts FROM issue_deliveries")
+            .fetch_one(&app.db_pool)
+            .await
+            .expect("Failed to fetch issue delivery.");
+    assert_eq!(delivery.status, "failed");
+    assert_eq!(delivery.attempts, 1);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let report = retry_failed_deliveries(
+        &app.db_pool,
+        &app.email_client,
+        &app.address,
+        &app.token_hasher,
+        delivery.newsletter_issue_id,
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(report.sent, 1);
+    assert_eq!(report.failed, 0);
+    let delivery = sqlx::query!("SELECT status, attempts FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch issue delivery.");
+    assert_eq!(delivery.status, "sent");
+    assert_eq!(delivery.attempts, 2);
+}
+
+#[tokio::test]
+async fn emails_follow_the_format_picked_and_link_to_the_preferences() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = app.preferences_token("scottyp@domain.com").await;
+    app.post_preferences_json(
+        &token,
+        &serde_json::json!({
+            "name": "scott pilgrim",
+            "email_format": "plain_text",
+            "frequency": "every_issue",
+        }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_body(None))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body.get("HtmlBody").is_none());
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(text.starts_with("Newsletter body as plain text"));
+    assert!(text.contains(&format!("/preferences?token={token}")));
+    assert!(text.ends_with(&format!("/unsubscribe?token={token}")));
+}
+
+#[tokio::test]
+async fn weekly_digest_subscribers_do_not_get_every_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = app.preferences_token("scottyp@domain.com").await;
+    app.post_preferences_json(
+        &token,
+        &serde_json::json!({
+            "name": "scott pilgrim",
+            "email_format": "html",
+            "frequency": "weekly_digest",
+        }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_body(None)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn issues_about_a_topic_only_go_to_subscribers_interested_in_it() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.topics = ["releases", "events"]
+            .map(|id| Topic {
+                id: id.into(),
+                name: id.into(),
+            })
+            .into()
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+    let token = app.preferences_token("scottyp@domain.com").await;
+    app.post_preferences_json(
+        &token,
+        &serde_json::json!({
+            "name": "scott pilgrim",
+            "email_format": "html",
+            "frequency": "every_issue",
+            "topics": ["releases"],
+        }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    // Act
+    for topic in ["events", "releases"] {
+        app.post_newsletters(newsletter_body(Some(topic)))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Assert
+    let topics: Vec<_> = sqlx::query!(
+        r#"
+        SELECT i.topic AS "topic!"
+        FROM issue_deliveries d
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .expect("Failed to fetch issue deliveries.")
+    .into_iter()
+    .map(|r| r.topic)
+    .collect();
+    assert_eq!(topics, ["releases"]);
+}
+
+#[tokio::test]
+async fn issues_about_an_unknown_topic_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(Some("gardening")))
+        .await;
+
+    // Assert
+    assert_problem(response, 400, "unknown-topic").await;
+}
+
+/// Subscribe and confirm `email`, then tag it.
+async fn create_tagged_subscriber(app: &TestApp, email: &str, source: &str, tags: &[&str]) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions_json(&serde_json::json!({
+        "name": "Tagged",
+        "email": email,
+        "source": source,
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    let subscriber_id = sqlx::query!(
+        "UPDATE subscriptions SET status = 'confirmed' WHERE email = $1 RETURNING id",
+        email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to confirm the subscriber.")
+    .id;
+    for tag in tags {
+        sqlx::query!(
+            "INSERT INTO subscriber_tags (subscriber_id, tag) VALUES ($1, $2)",
+            subscriber_id,
+            *tag
+        )
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to tag the subscriber.");
+    }
+}
+
+#[tokio::test]
+async fn dry_runs_count_the_subscribers_in_a_segment() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tagged_subscriber(&app, "rustacean@example.com", "blog", &["rust"]).await;
+    create_tagged_subscriber(&app, "asyncer@example.com", "conference", &["async"]).await;
+    create_tagged_subscriber(&app, "gopher@example.com", "blog", &["go"]).await;
+
+    for (segment, expected) in [
+        ("tag in [rust, async]", 2),
+        ("tag in [rust, async] AND source = blog", 1),
+        ("NOT tag = go", 2),
+        ("subscribed_at > 2025-01-01", 3),
+        ("subscribed_at < 2025-01-01", 0),
+    ] {
+        // Act
+        let response = app
+            .post_newsletters_dry_run(serde_json::json!({ "segment": segment }))
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["recipients"], expected, "{segment}");
+    }
+}
+
+#[tokio::test]
+async fn issues_with_a_segment_only_go_to_its_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tagged_subscriber(&app, "rustacean@example.com", "blog", &["rust"]).await;
+    create_tagged_subscriber(&app, "gopher@example.com", "blog", &["go"]).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let mut body = newsletter_body(None);
+    body["segment"] = "tag = rust".into();
+    let response = app.post_newsletters(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let delivery = sqlx::query!(
+        r#"
+        SELECT d.subscriber_email, i.segment
+        FROM issue_deliveries d
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch issue delivery.");
+    assert_eq!(delivery.subscriber_email, "rustacean@example.com");
+    assert_eq!(delivery.segment.as_deref(), Some("tag = rust"));
+}
+
+#[tokio::test]
+async fn invalid_segments_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters_dry_run(serde_json::json!({ "segment": "tag in [rust" }))
+        .await;
+
+    // Assert
+    assert_problem(response, 400, "invalid-segment").await;
+}
+
+#[tokio::test]
+async fn dry_runs_require_the_admin_token() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters/dry-run", app.address))
+        .json(&serde_json::json!({ "segment": "attr.plan = pro" }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_problem(response, 401, "missing-credentials").await;
+}
+
+fn newsletter_body(topic: Option<&str>) -> Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text"
+        },
+        "topic": topic,
+    })
+}
+
 async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
     let body = "name=scott%20pilgrim&email=scottyp%40domain.com";
 
@@ -108,6 +463,7 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .await
         .error_for_status()
         .unwrap();
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app
         .email_server
@@ -117,7 +473,7 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .pop()
         .unwrap();
 
-    app.get_confirmation_links(&email_request)
+    app.get_confirmation_links(email_request)
 }
 
 async fn create_confirmed_subscriber(app: &TestApp) {
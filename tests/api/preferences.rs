@@ -0,0 +1,217 @@
+use serde_json::{json, Value};
+use zero2prod::configuration::Topic;
+
+use crate::helpers::{assert_problem, spawn_app, spawn_app_with, TestApp};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn subscribe(app: &TestApp) -> String {
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.preferences_token(EMAIL).await
+}
+
+async fn spawn_app_with_topics() -> TestApp {
+    spawn_app_with(|c| {
+        c.topics = vec![
+            Topic {
+                id: "releases".into(),
+                name: "Release notes".into(),
+            },
+            Topic {
+                id: "events".into(),
+                name: "Events".into(),
+            },
+        ]
+    })
+    .await
+}
+
+#[tokio::test]
+async fn the_preferences_page_shows_the_current_preferences() {
+    // Arrange
+    let app = spawn_app_with_topics().await;
+    let token = subscribe(&app).await;
+
+    // Act
+    let response = app.get_preferences(&token, "text/html").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(EMAIL));
+    assert!(html.contains(r#"name="name" value="le guin""#));
+    assert!(html.contains(r#"value="html" checked"#));
+    assert!(html.contains(r#"value="every_issue" checked"#));
+    assert!(html.contains(r#"name="topic.releases">"#));
+}
+
+#[tokio::test]
+async fn json_clients_get_the_preferences_and_the_available_topics() {
+    // Arrange
+    let app = spawn_app_with_topics().await;
+    let token = subscribe(&app).await;
+
+    // Act
+    let response = app.get_preferences(&token, "application/json").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "email": EMAIL,
+            "name": "le guin",
+            "email_format": "html",
+            "frequency": "every_issue",
+            "topics": [],
+            "available_topics": [
+                {"id": "releases", "name": "Release notes"},
+                {"id": "events", "name": "Events"},
+            ],
+        })
+    );
+}
+
+#[tokio::test]
+async fn forged_preferences_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = subscribe(&app).await;
+    let forged = format!("{}0", token.trim_end_matches(char::is_alphanumeric));
+
+    // Act
+    let page = app.get_preferences(&forged, "text/html").await;
+    let problem = app.get_preferences(&forged, "application/json").await;
+
+    // Assert
+    assert_eq!(page.status().as_u16(), 404);
+    assert!(page
+        .text()
+        .await
+        .unwrap()
+        .contains("This link is not valid"));
+    assert_problem(problem, 404, "invalid-token").await;
+}
+
+#[tokio::test]
+async fn preferences_are_updated_with_json() {
+    // Arrange
+    let app = spawn_app_with_topics().await;
+    let token = subscribe(&app).await;
+
+    // Act
+    let response = app
+        .post_preferences_json(
+            &token,
+            &json!({
+                "name": "Ursula",
+                "email_format": "plain_text",
+                "frequency": "weekly_digest",
+                "topics": ["releases"],
+            }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["name"], "Ursula");
+    assert_eq!(body["topics"], json!(["releases"]));
+
+    let saved = sqlx::query!("SELECT name, email_format, frequency FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.name, "Ursula");
+    assert_eq!(saved.email_format, "plain_text");
+    assert_eq!(saved.frequency, "weekly_digest");
+}
+
+#[tokio::test]
+async fn preferences_are_updated_from_the_html_form() {
+    // Arrange
+    let app = spawn_app_with_topics().await;
+    let token = subscribe(&app).await;
+    app.post_preferences_json(
+        &token,
+        &json!({
+            "name": "le guin",
+            "email_format": "html",
+            "frequency": "every_issue",
+            "topics": ["releases"],
+        }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_preferences_form(
+            &token,
+            &[
+                ("name", "le guin"),
+                ("email_format", "html"),
+                ("frequency", "every_issue"),
+                ("topic.events", "on"),
+            ],
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Your preferences were saved."));
+    assert!(html.contains(r#"name="topic.events" checked>"#));
+
+    let topics: Vec<_> = sqlx::query!("SELECT topic FROM subscriber_topics")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved topics.")
+        .into_iter()
+        .map(|r| r.topic)
+        .collect();
+    assert_eq!(topics, ["events"]);
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected_with_every_problem() {
+    // Arrange
+    let app = spawn_app_with_topics().await;
+    let token = subscribe(&app).await;
+
+    // Act
+    let response = app
+        .post_preferences_json(
+            &token,
+            &json!({
+                "name": " ",
+                "email_format": "html",
+                "frequency": "every_issue",
+                "topics": ["gardening"],
+            }),
+        )
+        .await;
+
+    // Assert
+    let problem = assert_problem(response, 400, "validation-error").await;
+    let invalid_params: Vec<_> = problem["invalid_params"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| (p["name"].as_str().unwrap(), p["type"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        invalid_params,
+        [("name", "empty-name"), ("topics", "unknown-topic")]
+    );
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.name, "le guin");
+}
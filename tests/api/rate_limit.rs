@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::configuration::{RateLimit, RateLimitStoreKind, RouteRateLimits, Settings};
+
+use crate::helpers::{assert_problem, spawn_app_with, TestApp};
+
+fn limit_subscribe(
+    configuration: &mut Settings,
+    per_ip: Option<RateLimit>,
+    per_email: Option<RateLimit>,
+) {
+    configuration.rate_limit.routes = HashMap::from([(
+        "subscribe".to_string(),
+        RouteRateLimits { per_ip, per_email },
+    )]);
+}
+
+fn once_a_minute() -> Option<RateLimit> {
+    Some(RateLimit {
+        requests: 1,
+        per_seconds: 60,
+    })
+}
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn post_subscriptions_from(app: &TestApp, body: &str, client: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Forwarded-For", client)
+        .body(body.to_string())
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn subscribe_returns_429_with_retry_after_once_the_ip_limit_is_exceeded() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        let per_ip = Some(RateLimit {
+            requests: 2,
+            per_seconds: 60,
+        });
+        limit_subscribe(c, per_ip, None);
+    })
+    .await;
+    mock_email_server(&app).await;
+
+    // Act
+    for i in 0..2 {
+        let body = format!("name=le%20guin&email=ursula{i}%40gmail.com");
+        assert_eq!(200, app.post_subscriptions(body).await.status().as_u16());
+    }
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula2%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.headers()["Retry-After"], "30");
+    // Errors raised by middleware carry a correlation id too.
+    assert_problem(response, 429, "rate-limited").await;
+}
+
+#[tokio::test]
+async fn subscribe_returns_429_when_the_same_email_is_submitted_too_often() {
+    // Arrange
+    let app = spawn_app_with(|c| limit_subscribe(c, None, once_a_minute())).await;
+    mock_email_server(&app).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // Act
+    let first = app.post_subscriptions(body.into()).await;
+    let second = app
+        .post_subscriptions("name=le%20guin&email=URSULA_LE_GUIN%40gmail.com".into())
+        .await;
+    let other_email = app
+        .post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(429, second.status().as_u16());
+    assert_eq!(200, other_email.status().as_u16());
+}
+
+#[tokio::test]
+async fn forwarded_for_identifies_clients_behind_a_trusted_proxy() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.trusted_proxies = vec!["127.0.0.1/32".parse().unwrap()];
+        limit_subscribe(c, once_a_minute(), None);
+    })
+    .await;
+    mock_email_server(&app).await;
+
+    // Act
+    let first = post_subscriptions_from(
+        &app,
+        "name=le%20guin&email=ursula%40gmail.com",
+        "198.51.100.1",
+    )
+    .await;
+    let other_client = post_subscriptions_from(
+        &app,
+        "name=le%20guin&email=ursula1%40gmail.com",
+        "198.51.100.2",
+    )
+    .await;
+    let same_client = post_subscriptions_from(
+        &app,
+        "name=le%20guin&email=ursula2%40gmail.com",
+        "198.51.100.1",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(200, other_client.status().as_u16());
+    assert_eq!(429, same_client.status().as_u16());
+}
+
+#[tokio::test]
+async fn forwarded_for_is_ignored_without_trusted_proxies() {
+    // Arrange
+    let app = spawn_app_with(|c| limit_subscribe(c, once_a_minute(), None)).await;
+    mock_email_server(&app).await;
+
+    // Act
+    post_subscriptions_from(
+        &app,
+        "name=le%20guin&email=ursula%40gmail.com",
+        "198.51.100.1",
+    )
+    .await;
+    let response = post_subscriptions_from(
+        &app,
+        "name=le%20guin&email=ursula1%40gmail.com",
+        "198.51.100.2",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(429, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn postgres_store_keeps_buckets_in_the_database() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.store = RateLimitStoreKind::Postgres;
+        limit_subscribe(c, once_a_minute(), None);
+    })
+    .await;
+    mock_email_server(&app).await;
+
+    // Act
+    let first = app
+        .post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into())
+        .await;
+    let second = app
+        .post_subscriptions("name=le%20guin&email=ursula1%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(429, second.status().as_u16());
+    let buckets = sqlx::query!("SELECT key FROM rate_limit_buckets")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch rate limit buckets.");
+    assert_eq!(buckets.len(), 1);
+    assert!(buckets[0].key.starts_with("subscribe:ip:"));
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::configuration::{RateLimit, RouteRateLimits};
+
+use crate::helpers::{assert_problem, spawn_app, spawn_app_with, TestApp};
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn subscribe(app: &TestApp) {
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn resending_sends_a_new_link_and_invalidates_the_old_one() {
+    // Arrange
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    subscribe(&app).await;
+
+    // Act
+    let response = app
+        .post_resend_confirmation("Ursula_Le_Guin@gmail.com")
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(email_requests.len(), 2);
+    let old_link = app.get_confirmation_links(&email_requests[0]).html;
+    let new_link = app.get_confirmation_links(&email_requests[1]).html;
+    assert_ne!(old_link, new_link);
+
+    let response = reqwest::get(old_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+    let response = reqwest::get(new_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_response_does_not_tell_whether_the_address_is_subscribed() {
+    // Arrange
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    subscribe(&app).await;
+
+    // Act
+    let pending = app
+        .post_resend_confirmation("ursula_le_guin@gmail.com")
+        .await;
+    let unknown = app.post_resend_confirmation("someone_else@gmail.com").await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(pending.status(), unknown.status());
+    assert_eq!(pending.text().await.unwrap(), unknown.text().await.unwrap());
+    // One email to subscribe, one to resend, none for the unknown address.
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_not_sent_anything() {
+    // Arrange
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    subscribe(&app).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_resend_confirmation("ursula_le_guin@gmail.com")
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn resending_is_rate_limited_per_address() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.routes = HashMap::from([(
+            "resend_confirmation".to_string(),
+            RouteRateLimits {
+                per_ip: None,
+                per_email: Some(RateLimit {
+                    requests: 1,
+                    per_seconds: 60,
+                }),
+            },
+        )]);
+    })
+    .await;
+    mock_email_server(&app).await;
+
+    // Act
+    let first = app
+        .post_resend_confirmation("ursula_le_guin@gmail.com")
+        .await;
+    let second = app
+        .post_resend_confirmation("Ursula_Le_Guin@gmail.com")
+        .await;
+    let other = app.post_resend_confirmation("someone_else@gmail.com").await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_problem(second, 429, "rate-limited").await;
+    assert_eq!(other.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn invalid_addresses_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_resend_confirmation("not-an-email").await;
+
+    // Assert
+    assert_problem(response, 400, "validation-error").await;
+}
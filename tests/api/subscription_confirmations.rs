@@ -3,7 +3,24 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::spawn_app;
+use crate::helpers::{assert_problem, spawn_app, spawn_app_with, TestApp};
+
+/// Subscribe and return the confirmation link from the email.
+async fn subscribe(app: &TestApp) -> reqwest::Url {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=scott%20pilgrim&email=scottyp%40domain.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_confirmation_links(email_request).html
+}
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -19,7 +36,33 @@ async fn confirmations_without_token_are_rejected_with_a_400() {
     .unwrap();
 
     // Assert
-    assert_eq!(response.status().as_u16(), 400)
+    assert_problem(response, 400, "malformed-request").await;
+}
+
+#[tokio::test]
+async fn confirmations_with_an_unknown_token_show_the_invalid_link_page() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("This link is not valid"));
 }
 
 #[tokio::test]
@@ -35,6 +78,7 @@ async fn link_returned_by_subscribe_returns_a_200_if_called() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(email_request);
 
@@ -58,6 +102,7 @@ async fn clicking_on_confirmation_link_confirms_subscriber() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(email_request);
 
@@ -78,3 +123,108 @@ async fn clicking_on_confirmation_link_confirms_subscriber() {
     assert_eq!(saved.name, "scott pilgrim");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn confirming_shows_a_confirmation_page() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = subscribe(&app).await;
+
+    // Act
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Subscription confirmed"));
+}
+
+#[tokio::test]
+async fn following_a_link_twice_says_the_subscription_is_already_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = subscribe(&app).await;
+    reqwest::get(link.clone()).await.unwrap();
+
+    // Act
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Already confirmed"));
+}
+
+#[tokio::test]
+async fn expired_links_offer_to_resend_the_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = subscribe(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '73 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"action="/subscriptions/resend-confirmation""#));
+    assert!(page.contains(r#"value="scottyp@domain.com""#));
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn outcomes_with_a_configured_redirect_are_sent_there() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.confirmation.redirects.confirmed = Some("https://example.com/welcome".into());
+    })
+    .await;
+    let link = subscribe(&app).await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    // Act
+    let response = client.get(link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(
+        response.headers()["Location"],
+        "https://example.com/welcome"
+    );
+}
+
+#[tokio::test]
+async fn only_a_hash_of_the_token_is_stored() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let link = subscribe(&app).await;
+
+    // Assert
+    let (_, token) = link
+        .query_pairs()
+        .find(|(name, _)| name == "subscription_token")
+        .unwrap();
+    assert_eq!(token.len(), 64);
+    let stored = sqlx::query!("SELECT token_hash FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+    assert!(!stored.token_hash.contains(token.as_ref()));
+}
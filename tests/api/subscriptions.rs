@@ -1,9 +1,11 @@
+use serde_json::json;
 use wiremock::{
     matchers::{method, path},
-    Mock, ResponseTemplate,
+    Mock, MockServer, ResponseTemplate,
 };
+use zero2prod::configuration::{get_configuration, RuntimeSettings};
 
-use crate::helpers::spawn_app;
+use crate::helpers::{assert_problem, spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -49,6 +51,30 @@ async fn subscribe_persists_new_subscriber() {
     assert_eq!(saved.status, "pending_confirmation");
 }
 
+#[tokio::test]
+async fn subscribe_stores_the_canonical_email_and_keeps_the_original_for_display() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=test%20user&email=%20TestUser%40GMail.com%20";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(body.into()).await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT email, display_email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "TestUser@gmail.com");
+    assert_eq!(saved.display_email, "TestUser@GMail.com");
+}
+
 #[tokio::test]
 async fn subscribe_returns_a_400_when_data_is_missing() {
     // Arrange
@@ -97,6 +123,231 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
     }
 }
 
+#[tokio::test]
+async fn subscribe_explains_why_an_email_is_not_accepted() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.email_policy.reject_role_addresses = true;
+        c.email_policy.denied_domains = vec!["example.org".into()];
+    })
+    .await;
+    let test_cases = vec![
+        (
+            "name=Ursula&email=ursula%40mailinator.com",
+            "disposable-email",
+            "Addresses at mailinator.com are not accepted: it is a disposable email provider.",
+        ),
+        (
+            "name=Ursula&email=ursula%40example.org",
+            "denied-email-domain",
+            "Addresses at example.org are not accepted.",
+        ),
+        (
+            "name=Ursula&email=postmaster%40gmail.com",
+            "role-address",
+            "postmaster@gmail.com is a role address, please subscribe with a personal address.",
+        ),
+    ];
+
+    for (body, problem_type, reason) in test_cases {
+        // Act
+        let response = app.post_subscriptions(body.into()).await;
+
+        // Assert
+        let problem = assert_problem(response, 400, "validation-error").await;
+        assert_eq!(
+            problem["invalid_params"],
+            json!([{ "name": "email", "type": problem_type, "reason": reason }])
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribe_tells_a_bad_name_from_a_bad_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            "name=&email=ursula_le_guin%40gmail.com",
+            "name",
+            "empty-name",
+        ),
+        (
+            "name=Ursula&email=definitely-not-an-email",
+            "email",
+            "invalid-email",
+        ),
+    ];
+
+    for (body, field, problem_type) in test_cases {
+        // Act
+        let response = app.post_subscriptions(body.into()).await;
+
+        // Assert
+        let problem = assert_problem(response, 400, "validation-error").await;
+        assert_eq!(problem["invalid_params"][0]["name"], field);
+        assert_eq!(problem["invalid_params"][0]["type"], problem_type);
+    }
+}
+
+#[tokio::test]
+async fn subscribe_reports_every_invalid_field_at_once() {
+    // Arrange
+    let app = spawn_app().await;
+    // `{` and `}` around a name that is too long already.
+    let body = format!(
+        "name=%7B{}%7D&email=definitely-not-an-email",
+        "a".repeat(256)
+    );
+
+    // Act
+    let response = app.post_subscriptions(body).await;
+
+    // Assert
+    let problem = assert_problem(response, 400, "validation-error").await;
+    let invalid_params: Vec<_> = problem["invalid_params"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| (p["name"].as_str().unwrap(), p["type"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        invalid_params,
+        [
+            ("name", "name-too-long"),
+            ("name", "forbidden-characters"),
+            ("email", "invalid-email"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn subscribe_reports_undecodable_bodies_as_malformed() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_subscriptions("name=Ursula".into()).await;
+
+    // Assert
+    assert_problem(response, 400, "malformed-request").await;
+}
+
+#[tokio::test]
+async fn subscribe_accepts_json_and_answers_with_the_subscription_status() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions_json(&json!({"name": "test user", "email": "testuser@gmail.com"}))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body, json!({"status": "pending_confirmation"}));
+
+    let saved = sqlx::query!("SELECT email, name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "testuser@gmail.com");
+    assert_eq!(saved.name, "test user");
+}
+
+#[tokio::test]
+async fn subscribe_records_where_subscribers_signed_up() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&source=%20blog%20".into(),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    app.post_subscriptions_json(&json!({"name": "test user", "email": "testuser@gmail.com"}))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let sources: Vec<_> = sqlx::query!("SELECT signup_source FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.")
+        .into_iter()
+        .map(|r| r.signup_source)
+        .collect();
+    assert_eq!(sources, [None, Some("blog".to_string())]);
+}
+
+#[tokio::test]
+async fn subscribe_validates_json_bodies_like_forms() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions_json(&json!({"name": "", "email": "not-an-email"}))
+        .await;
+
+    // Assert
+    let problem = assert_problem(response, 400, "validation-error").await;
+    let fields: Vec<_> = problem["invalid_params"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|param| param["name"].clone())
+        .collect();
+    assert_eq!(fields, [json!("name"), json!("email")]);
+}
+
+#[tokio::test]
+async fn subscribe_reports_undecodable_json_as_malformed() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions_json(&json!({"name": "le guin"}))
+        .await;
+
+    // Assert
+    assert_problem(response, 400, "malformed-request").await;
+}
+
+#[tokio::test]
+async fn subscribe_answers_forms_with_an_empty_body() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().is_empty());
+}
+
 #[tokio::test]
 async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     // Arrange
@@ -112,6 +363,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
 
     // Act
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -128,6 +380,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
 
     // Act
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     // Assert
     // Get the first intercepted request
@@ -137,6 +390,66 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 }
 
+#[tokio::test]
+async fn subscribing_twice_answers_like_the_first_time_and_resends_the_link() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let first = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Act
+    let second = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    assert_eq!(first.text().await.unwrap(), second.text().await.unwrap());
+    let subscribers = sqlx::query_scalar!("SELECT COUNT(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers, Some(1));
+}
+
+#[tokio::test]
+async fn subscribing_a_confirmed_address_again_sends_nothing() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("DELETE FROM email_outbox")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
 #[tokio::test]
 async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Arrange
@@ -152,5 +465,42 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     let response = app.post_subscriptions(body.into()).await;
 
     // Assert
-    assert_eq!(response.status().as_u16(), 500)
+    let problem = assert_problem(response, 500, "internal-error").await;
+    assert!(
+        !problem.to_string().contains("column"),
+        "The database error leaked into the response: {problem}"
+    );
+}
+
+#[tokio::test]
+async fn subscribe_uses_reloaded_email_settings() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=test%20user&email=testuser%40gmail.com";
+    let reloaded_email_server = MockServer::start().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&reloaded_email_server)
+        .await;
+
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.email_client.base_url = reloaded_email_server.uri();
+    app.runtime_settings
+        .replace(RuntimeSettings::new(&configuration).expect("Failed to build runtime settings"));
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
 }
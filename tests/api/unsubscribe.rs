@@ -0,0 +1,140 @@
+use serde_json::{json, Value};
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_problem, spawn_app, TestApp};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+const SUBSCRIPTION: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+/// A confirmed subscriber, and the token of the links in their emails.
+async fn create_confirmed_subscriber(app: &TestApp) -> String {
+    app.post_subscriptions(SUBSCRIPTION.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("DELETE FROM email_outbox")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.preferences_token(EMAIL).await
+}
+
+async fn status(app: &TestApp) -> String {
+    sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn following_the_link_asks_before_unsubscribing() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app.get_unsubscribe(&token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(EMAIL));
+    assert!(html.contains(&format!(
+        r#"<form action="/unsubscribe?token={token}" method="post">"#
+    )));
+    assert_eq!(status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_get_no_more_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_unsubscribe(&token, "application/json").await;
+    app.post_newsletters(json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "unsubscribed");
+    assert_eq!(status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribing_twice_is_fine() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_confirmed_subscriber(&app).await;
+    app.post_unsubscribe(&token, "text/html").await;
+
+    // Act
+    let response = app.post_unsubscribe(&token, "text/html").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("You are unsubscribed"));
+}
+
+#[tokio::test]
+async fn forged_links_do_not_unsubscribe_anyone() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_confirmed_subscriber(&app).await;
+    let forged = format!("{}0", token);
+
+    // Act
+    let response = app.post_unsubscribe(&forged, "application/json").await;
+
+    // Assert
+    assert_problem(response, 404, "invalid-token").await;
+    assert_eq!(status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_addresses_can_subscribe_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_confirmed_subscriber(&app).await;
+    app.post_unsubscribe(&token, "text/html").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(SUBSCRIPTION.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(status(&app).await, "pending_confirmation");
+}